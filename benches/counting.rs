@@ -0,0 +1,35 @@
+use std::time::Instant;
+
+use lox::run;
+
+const ITERATIONS: u32 = 10;
+
+// The same counting loop twice: the first body only assigns arithmetic, so `while` runs it
+// without a scope per iteration; the `var` in the second keeps it on the general path.
+fn counting_source(
+    count: usize,
+    prefix: &str,
+) -> String {
+    format!(
+        "var i = 0;\nvar total = 0;\nwhile i < {} {{ {}total = total + i % 7; i = i + 1; }}\n",
+        count, prefix
+    )
+}
+
+// Run with `cargo bench --bench counting`.
+fn main() {
+    for count in [10_000, 100_000] {
+        for (path, prefix) in [("fast path", ""), ("general path", "var step = 1; ")] {
+            let source = counting_source(count, prefix);
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                if let Err(e) = run(&source) {
+                    panic!("counting to {} failed: {}", count, e);
+                }
+            }
+            let elapsed = start.elapsed() / ITERATIONS;
+            let per_second = count as f64 / elapsed.as_secs_f64();
+            println!("count {:>7} {:<12}: {:?} per run, {:.0} iterations/s", count, path, elapsed, per_second);
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::time::Instant;
+
+use lox::run;
+
+const ITERATIONS: u32 = 10;
+
+// Lox has no functions yet, so there is no recursive version. The sequence is unrolled into one
+// declaration per term rather than looped, so lexing and parsing a large source are measured too.
+fn fibonacci_source(terms: usize) -> String {
+    let mut source = String::from("var f0 = 0;\nvar f1 = 1;\n");
+    for i in 2..terms {
+        source.push_str(&format!("var f{} = (f{} + f{}) % 1000000007;\n", i, i - 1, i - 2));
+    }
+    source.push_str(&format!("print f{};\n", terms - 1));
+    source
+}
+
+// Run with `cargo bench --bench fibonacci`.
+fn main() {
+    for terms in [100, 1_000, 10_000] {
+        let source = fibonacci_source(terms);
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            if let Err(e) = run(&source) {
+                panic!("fibonacci({}) failed: {}", terms, e);
+            }
+        }
+        println!("fibonacci {:>6} terms: {:?} per run", terms, start.elapsed() / ITERATIONS);
+    }
+}
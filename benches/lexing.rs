@@ -0,0 +1,89 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use lox::lexing::lexing;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(
+        &self,
+        layout: Layout,
+    ) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+    ) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Run with `cargo bench --bench lexing`.
+//
+// `lexing` sizes its token vector from the source length up front. To show what that saves, the
+// lexed tokens are also pushed again into vectors started empty, at a quarter and at half the
+// source length: the reallocations are those `lexing` would do with each starting capacity.
+fn main() {
+    for lines in [1_000, 10_000, 100_000] {
+        let source = (0..lines).map(|i| format!("var v{} = (v{} + {}.5) * 2;\n", i, i, i)).collect::<String>();
+        let path = std::env::temp_dir().join(format!("lox_bench_{}.lox", lines));
+        std::fs::write(&path, &source).unwrap();
+
+        ALLOCATIONS.store(0, Ordering::Relaxed);
+        REALLOCATIONS.store(0, Ordering::Relaxed);
+        let start = Instant::now();
+        let tokens = lexing(path.to_str().unwrap()).unwrap();
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:>7} lines, {:>9} bytes, {:>8} tokens: {:>8} allocations, {:>3} reallocations, {:?}",
+            lines,
+            source.len(),
+            tokens.len(),
+            ALLOCATIONS.load(Ordering::Relaxed),
+            REALLOCATIONS.load(Ordering::Relaxed),
+            elapsed
+        );
+        for (name, capacity) in [("Vec::new()", 0), ("len / 4", source.len() / 4), ("len / 2", source.len() / 2)] {
+            REALLOCATIONS.store(0, Ordering::Relaxed);
+            let start = Instant::now();
+            let mut vec = Vec::with_capacity(capacity);
+            for token in &tokens {
+                vec.push(token.clone());
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "    {:<10} {:>3} reallocations, {:>8} unused slots, {:?}",
+                name,
+                REALLOCATIONS.load(Ordering::Relaxed),
+                vec.capacity() - vec.len(),
+                elapsed
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
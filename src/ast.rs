@@ -1,6 +1,9 @@
 use std::fmt::{Display, Formatter};
 
-use crate::token::Number;
+use crate::{
+    lexing::{number_literal, string_literal},
+    token::Number,
+};
 // expression     → equality ;
 // equality       → comparison ( ( "!=" | "==" ) comparison )* ;
 // comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
@@ -11,7 +14,7 @@ use crate::token::Number;
 // primary        → NUMBER | STRING | "true" | "false" | "nil"
 //                | "(" expression ")" ;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AstNode {
     Binary {
         left: Box<AstNode>,
@@ -28,11 +31,272 @@ pub enum AstNode {
         operand: Box<AstNode>,
     },
     Print(Box<AstNode>),
+    Return(Option<Box<AstNode>>),
+    Break(Option<String>),
+    Continue(Option<String>),
+    /// A loop that `break` and `continue` can name.
+    Labeled {
+        label: String,
+        body: Box<AstNode>,
+    },
+    ExprStmt(Box<AstNode>),
     Variable {
         name: String,
         value: Option<Box<AstNode>>,
     },
+    Const {
+        name: String,
+        value: Box<AstNode>,
+    },
+    Global {
+        name: String,
+        value: Box<AstNode>,
+    },
+    Import(String),
+    Destructure {
+        names: Vec<String>,
+        value: Box<AstNode>,
+    },
     Block(Vec<AstNode>),
+    Identifier {
+        name: String,
+        depth: Option<usize>,
+    },
+    Assign {
+        name: String,
+        value: Box<AstNode>,
+        depth: Option<usize>,
+    },
+    Switch {
+        scrutinee: Box<AstNode>,
+        cases: Vec<(AstNode, AstNode)>,
+        default: Option<Box<AstNode>>,
+    },
+    Match {
+        scrutinee: Box<AstNode>,
+        arms: Vec<MatchArm>,
+    },
+    If {
+        condition: Box<AstNode>,
+        then_branch: Box<AstNode>,
+        else_branch: Option<Box<AstNode>>,
+    },
+    Call {
+        callee: Box<AstNode>,
+        arguments: Vec<AstNode>,
+    },
+    Index {
+        object: Box<AstNode>,
+        index: Box<AstNode>,
+    },
+    List(Vec<AstNode>),
+    ForIn {
+        name: String,
+        iterable: Box<AstNode>,
+        body: Box<AstNode>,
+    },
+    While {
+        condition: Box<AstNode>,
+        body: Box<AstNode>,
+    },
+    Repeat {
+        count: Box<AstNode>,
+        body: Box<AstNode>,
+    },
+    Sequence(Vec<AstNode>),
+    Defer(Box<AstNode>),
+    NilCoalesce {
+        left: Box<AstNode>,
+        right: Box<AstNode>,
+    },
+}
+
+/// One arm of a `match`: runs `body` when the value's type name is `pattern`, or for any value
+/// when `pattern` is `_`, with the value bound to `binding` if there is one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: String,
+    pub binding: Option<String>,
+    pub body: AstNode,
+}
+
+impl MatchArm {
+    pub fn matches(
+        &self,
+        type_name: &str,
+    ) -> bool {
+        self.pattern == "_" || self.pattern == type_name
+    }
+}
+
+impl Display for MatchArm {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        match &self.binding {
+            Some(binding) => write!(f, "{} {} => {}", self.pattern, binding, self.body),
+            None => write!(f, "{} => {}", self.pattern, self.body),
+        }
+    }
+}
+
+impl AstNode {
+    /// Whether the value of this expression depends only on its own literals: no variables, calls
+    /// or lists anywhere inside it, so evaluating it again always gives the same result.
+    pub fn is_pure(&self) -> bool {
+        match self {
+            AstNode::Boolean(_) | AstNode::Nil | AstNode::Number(_) | AstNode::String(_) => true,
+            AstNode::Binary { left, operator, right } => operator != ".." && left.is_pure() && right.is_pure(),
+            AstNode::Unary { operand, .. } => operand.is_pure(),
+            AstNode::Group(inner) => inner.is_pure(),
+            _ => false,
+        }
+    }
+
+    /// Prints the node back as Lox source. Parentheses are written only where precedence needs
+    /// them, whether or not the original had them: `(1 + 2) * 3` keeps its group but `1 + (2 * 3)`
+    /// comes out as `1 + 2 * 3`. `unless` and `until` come out as `if !` and `while !`.
+    pub fn to_source(&self) -> String {
+        self.source(ASSIGNMENT)
+    }
+
+    /// Binding strength of an expression, following the grammar in `parsing`: an operand whose
+    /// precedence is below what its position requires needs parentheses.
+    fn precedence(&self) -> u8 {
+        match self {
+            AstNode::Assign { .. } => ASSIGNMENT,
+            AstNode::NilCoalesce { .. } => COALESCE,
+            AstNode::Binary { operator, .. } => match operator.as_str() {
+                "or" => OR,
+                "and" => AND,
+                "==" | "!=" => EQUALITY,
+                ">" | ">=" | "<" | "<=" => COMPARISON,
+                ".." => RANGE,
+                "+" | "-" => TERM,
+                "**" => POWER,
+                _ => FACTOR,
+            },
+            AstNode::Unary { .. } => UNARY,
+            AstNode::Call { .. } | AstNode::Index { .. } => CALL,
+            AstNode::Group(inner) => inner.precedence(),
+            _ => PRIMARY,
+        }
+    }
+
+    fn source(
+        &self,
+        min: u8,
+    ) -> String {
+        let source = match self {
+            AstNode::Group(inner) => return inner.source(min),
+            AstNode::Binary { left, operator, right } => {
+                let precedence = self.precedence();
+                let (left_min, right_min) = match precedence {
+                    RANGE => (precedence + 1, precedence + 1),
+                    POWER => (CALL, UNARY),
+                    _ => (precedence, precedence + 1),
+                };
+                format!("{} {} {}", left.source(left_min), operator, right.source(right_min))
+            }
+            AstNode::NilCoalesce { left, right } => format!("{} ?? {}", left.source(COALESCE), right.source(COALESCE + 1)),
+            AstNode::Unary { operator, operand } => format!("{}{}", operator, operand.source(UNARY)),
+            AstNode::Assign { name, value, .. } => format!("{} = {}", name, value.source(ASSIGNMENT)),
+            AstNode::Call { callee, arguments } => format!("{}({})", callee.source(CALL), sources(arguments, ", ")),
+            AstNode::Index { object, index } => format!("{}[{}]", object.source(CALL), index.to_source()),
+            AstNode::List(elements) => format!("[{}]", sources(elements, ", ")),
+            AstNode::Sequence(elements) => format!("({})", sources(elements, ", ")),
+            AstNode::Boolean(v) => v.to_string(),
+            AstNode::Nil => "nil".to_string(),
+            AstNode::Number(number) => number_literal(*number),
+            AstNode::String(s) => string_literal(s),
+            AstNode::Identifier { name, .. } => name.clone(),
+            AstNode::Print(v) => format!("print {};", v.to_source()),
+            AstNode::Return(Some(v)) => format!("return {};", v.to_source()),
+            AstNode::Return(None) => "return;".to_string(),
+            AstNode::Break(Some(label)) => format!("break {};", label),
+            AstNode::Break(None) => "break;".to_string(),
+            AstNode::Continue(Some(label)) => format!("continue {};", label),
+            AstNode::Continue(None) => "continue;".to_string(),
+            AstNode::Labeled { label, body } => format!("{}: {}", label, body.to_source()),
+            AstNode::ExprStmt(v) => format!("{};", v.to_source()),
+            AstNode::Variable { name, value: Some(value) } => format!("var {} = {};", name, value.to_source()),
+            AstNode::Variable { name, value: None } => format!("var {};", name),
+            AstNode::Const { name, value } => format!("const {} = {};", name, value.to_source()),
+            AstNode::Global { name, value } => format!("global {} = {};", name, value.to_source()),
+            AstNode::Import(path) => format!("import {};", string_literal(path)),
+            AstNode::Destructure { names, value } => format!("var [{}] = {};", names.join(", "), value.to_source()),
+            AstNode::Block(nodes) if nodes.is_empty() => "{}".to_string(),
+            AstNode::Block(nodes) => format!("{{ {} }}", sources(nodes, " ")),
+            AstNode::Defer(statement) => format!("defer {}", statement.to_source()),
+            AstNode::Switch { scrutinee, cases, default } => {
+                let mut source = format!("switch {} {{", scrutinee.to_source());
+                for (value, body) in cases {
+                    source += &format!(" case {}: {}", value.to_source(), body.to_source());
+                }
+                if let Some(default) = default {
+                    source += &format!(" default: {}", default.to_source());
+                }
+                source + " }"
+            }
+            AstNode::Match { scrutinee, arms } => {
+                let arms = arms
+                    .iter()
+                    .map(|arm| match &arm.binding {
+                        Some(binding) => format!("{} {} => {}", arm.pattern, binding, arm.body.to_source()),
+                        None => format!("{} => {}", arm.pattern, arm.body.to_source()),
+                    })
+                    .collect::<Vec<String>>();
+                format!("match {} {{ {} }}", scrutinee.to_source(), arms.join(", "))
+            }
+            AstNode::If {
+                condition,
+                then_branch,
+                else_branch: Some(else_branch),
+            } => {
+                // An else-less `if` as the then branch would otherwise capture our `else`.
+                let then_branch = match **then_branch {
+                    AstNode::If { else_branch: None, .. } => format!("{{ {} }}", then_branch.to_source()),
+                    _ => then_branch.to_source(),
+                };
+                format!("if {} {} else {}", condition.to_source(), then_branch, else_branch.to_source())
+            }
+            AstNode::If {
+                condition,
+                then_branch,
+                else_branch: None,
+            } => format!("if {} {}", condition.to_source(), then_branch.to_source()),
+            AstNode::ForIn { name, iterable, body } => format!("for {} in {} {}", name, iterable.to_source(), body.to_source()),
+            AstNode::While { condition, body } => format!("while {} {}", condition.to_source(), body.to_source()),
+            AstNode::Repeat { count, body } => format!("repeat {} {}", count.to_source(), body.to_source()),
+        };
+        if self.precedence() < min {
+            format!("({})", source)
+        } else {
+            source
+        }
+    }
+}
+
+const ASSIGNMENT: u8 = 1;
+const COALESCE: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 4;
+const EQUALITY: u8 = 5;
+const COMPARISON: u8 = 6;
+const RANGE: u8 = 7;
+const TERM: u8 = 8;
+const FACTOR: u8 = 9;
+const UNARY: u8 = 10;
+const POWER: u8 = 11;
+const CALL: u8 = 12;
+const PRIMARY: u8 = 13;
+
+fn sources(
+    nodes: &[AstNode],
+    separator: &str,
+) -> String {
+    nodes.iter().map(AstNode::to_source).collect::<Vec<String>>().join(separator)
 }
 
 impl Display for AstNode {
@@ -51,6 +315,14 @@ impl Display for AstNode {
             AstNode::String(s) => write!(f, "{}", s),
             AstNode::Unary { operator, operand } => write!(f, "({} {})", operator, operand),
             AstNode::Print(v) => write!(f, "Print {}", v),
+            AstNode::Return(Some(v)) => write!(f, "Return {}", v),
+            AstNode::Return(None) => write!(f, "Return"),
+            AstNode::Break(Some(label)) => write!(f, "Break {}", label),
+            AstNode::Break(None) => write!(f, "Break"),
+            AstNode::Continue(Some(label)) => write!(f, "Continue {}", label),
+            AstNode::Continue(None) => write!(f, "Continue"),
+            AstNode::Labeled { label, body } => write!(f, "{}: {}", label, body),
+            AstNode::ExprStmt(v) => write!(f, "ExprStmt {}", v),
             AstNode::Variable { name, value } => {
                 if let Some(value) = value {
                     write!(f, "Variable {} = {}", name, value)
@@ -58,6 +330,10 @@ impl Display for AstNode {
                     write!(f, "Variable {} = None", name)
                 }
             }
+            AstNode::Const { name, value } => write!(f, "Const {} = {}", name, value),
+            AstNode::Global { name, value } => write!(f, "Global {} = {}", name, value),
+            AstNode::Import(path) => write!(f, "Import {}", path),
+            AstNode::Destructure { names, value } => write!(f, "Variable [{}] = {}", names.join(", "), value),
             AstNode::Block(v) => {
                 write!(f, "Block [")?;
                 for node in v {
@@ -65,13 +341,81 @@ impl Display for AstNode {
                 }
                 write!(f, "]")
             }
+            AstNode::Identifier { name, .. } => write!(f, "{}", name),
+            AstNode::Assign { name, value, .. } => write!(f, "Assign {} = {}", name, value),
+            AstNode::Switch { scrutinee, cases, default } => {
+                write!(f, "Switch {} [", scrutinee)?;
+                for (value, body) in cases {
+                    write!(f, "case {}: {}, ", value, body)?;
+                }
+                if let Some(default) = default {
+                    write!(f, "default: {}, ", default)?;
+                }
+                write!(f, "]")
+            }
+            AstNode::Match { scrutinee, arms } => {
+                write!(f, "Match {} [", scrutinee)?;
+                for arm in arms {
+                    write!(f, "{}, ", arm)?;
+                }
+                write!(f, "]")
+            }
+            AstNode::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                write!(f, "If {} then {}", condition, then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    write!(f, " else {}", else_branch)?;
+                }
+                Ok(())
+            }
+            AstNode::Index { object, index } => write!(f, "{}[{}]", object, index),
+            AstNode::Call { callee, arguments } => {
+                write!(f, "{}(", callee)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            }
+            AstNode::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            AstNode::ForIn { name, iterable, body } => write!(f, "For {} in {} {}", name, iterable, body),
+            AstNode::While { condition, body } => write!(f, "While {} {}", condition, body),
+            AstNode::Repeat { count, body } => write!(f, "Repeat {} {}", count, body),
+            AstNode::Defer(statement) => write!(f, "Defer {}", statement),
+            AstNode::NilCoalesce { left, right } => write!(f, "(?? {} {})", left, right),
+            AstNode::Sequence(nodes) => {
+                write!(f, "(sequence")?;
+                for node in nodes {
+                    write!(f, " {}", node)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ast::AstNode, token::Number};
+    use crate::{
+        ast::AstNode,
+        lexing::{lexing, lexing_str},
+        parsing::Parser,
+        token::{Number, TokenType},
+    };
 
     #[test]
     fn display() {
@@ -88,4 +432,35 @@ mod tests {
 
         println!("{}", ast);
     }
+
+    #[test]
+    fn display_if() {
+        let tokens = lexing("tests/if.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let ast = Parser::new(tokens).parse().unwrap();
+        assert_eq!(ast[1].to_string(), "If (> x 1) then Print big else Print small");
+        assert_eq!(ast[3].to_string(), "If (< x 1) then Print never");
+    }
+
+    #[test]
+    fn to_source() {
+        let source = |source: &str| {
+            let tokens = lexing_str(source).unwrap().into_iter().filter(|token| !token.is_skippable()).collect();
+            Parser::new(tokens).parse().unwrap()[0].to_source()
+        };
+        assert_eq!(source("(1 + 2) * 3;"), "(1 + 2) * 3;");
+        assert_eq!(source("1 + (2 * 3);"), "1 + 2 * 3;");
+        assert_eq!(source("(1 - 2) - (3 - 4);"), "1 - 2 - (3 - 4);");
+        assert_eq!(source("(-2) ** 2 ** -1;"), "(-2) ** 2 ** -1;");
+        assert_eq!(source("-(2 ** 2);"), "-2 ** 2;");
+        assert_eq!(source("(a or b) and (c ?? d);"), "(a or b) and (c ?? d);");
+        assert_eq!(source("x = (y = 1) + 2;"), "x = (y = 1) + 2;");
+        assert_eq!(source("print f((1), [2.5, 3f, \"a\\\"b\"]);"), "print f(1, [2.5, 3f, \"a\\\"b\"]);");
+        assert_eq!(
+            source("if a if b print 1; else print 2; else print 3;"),
+            "if a if b print 1; else print 2; else print 3;"
+        );
+        assert_eq!(source("if a { if b print 1; } else print 2;"), "if a { if b print 1; } else print 2;");
+        assert_eq!(source("until i >= 3 { i = i + 1; }"), "while !(i >= 3) { i = i + 1; }");
+    }
 }
@@ -0,0 +1,755 @@
+use std::cmp::Ordering;
+
+use anyhow::bail;
+
+use crate::{
+    environment::Environment,
+    evaluating::{map_get, EvaluateResult, MapKey, NativeFunction, MAX_RANGE_LEN},
+    token::Number,
+};
+
+pub fn define_builtins(env: &mut Environment) {
+    let natives = [
+        NativeFunction::new("contains", 2, contains),
+        NativeFunction::new("indexOf", 2, index_of),
+        NativeFunction::new("globals", 0, globals),
+        NativeFunction::variadic("format", format),
+        NativeFunction::variadic("sort", sort),
+        NativeFunction::new("reduce", 3, reduce),
+        NativeFunction::new("trim", 1, trim),
+        NativeFunction::new("upper", 1, upper),
+        NativeFunction::new("lower", 1, lower),
+        NativeFunction::new("concat", 2, concat),
+        NativeFunction::new("push", 2, push),
+        NativeFunction::new("pop", 1, pop),
+        NativeFunction::variadic("map", map),
+        NativeFunction::new("filter", 2, filter),
+        NativeFunction::variadic("range", range),
+        NativeFunction::new("set", 3, set),
+        NativeFunction::new("get", 2, get),
+        NativeFunction::new("keys", 1, keys),
+        NativeFunction::new("write", 1, write),
+        NativeFunction::variadic("sprint", sprint),
+        NativeFunction::new("bool", 1, boolean),
+        NativeFunction::variadic("printf", printf),
+        NativeFunction::new("isNil", 1, is_nil),
+        NativeFunction::new("isNumber", 1, is_number),
+        NativeFunction::new("isString", 1, is_string),
+        NativeFunction::new("isBool", 1, is_bool),
+        NativeFunction::new("assertEq", 2, assert_eq),
+    ];
+    for native in natives {
+        let name = native.name.clone();
+        env.define(&name, EvaluateResult::NativeFunction(native));
+    }
+}
+
+fn one_string<'a>(
+    name: &str,
+    arguments: &'a [EvaluateResult],
+) -> anyhow::Result<&'a str> {
+    match arguments {
+        [EvaluateResult::String(s)] => Ok(s),
+        [other] => bail!("{} expects a string, got {}", name, other.type_name()),
+        _ => bail!("{} expects a string", name),
+    }
+}
+
+fn two_strings<'a>(
+    name: &str,
+    arguments: &'a [EvaluateResult],
+) -> anyhow::Result<(&'a str, &'a str)> {
+    match arguments {
+        [EvaluateResult::String(haystack), EvaluateResult::String(needle)] => Ok((haystack, needle)),
+        _ => bail!("{} expects two strings", name),
+    }
+}
+
+/// `contains(haystack, needle)`: whether `needle` occurs in `haystack`. The empty string is
+/// contained in every string.
+fn contains(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let (haystack, needle) = two_strings("contains", arguments)?;
+    Ok(EvaluateResult::Boolean(haystack.contains(needle)))
+}
+
+/// `trim(s)`: `s` without leading and trailing whitespace.
+fn trim(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::String(one_string("trim", arguments)?.trim().to_string()))
+}
+
+/// `upper(s)`: `s` with every character uppercased.
+fn upper(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::String(one_string("upper", arguments)?.to_uppercase()))
+}
+
+/// `lower(s)`: `s` with every character lowercased.
+fn lower(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::String(one_string("lower", arguments)?.to_lowercase()))
+}
+
+/// `indexOf(haystack, needle)`: the character (not byte) index of the first occurrence of
+/// `needle`, or -1 when it doesn't occur.
+fn index_of(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let (haystack, needle) = two_strings("indexOf", arguments)?;
+    let index = match haystack.find(needle) {
+        Some(byte_index) => haystack[..byte_index].chars().count() as i64,
+        None => -1,
+    };
+    Ok(EvaluateResult::Number(Number::Integer(index)))
+}
+
+/// `globals()`: the sorted names defined in the global environment, builtins included.
+fn globals(
+    env: &mut Environment,
+    _arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::list(env.global_names().into_iter().map(EvaluateResult::String).collect()))
+}
+
+/// `concat(a, b)`: a new list with the elements of `a` followed by those of `b`. Neither input is
+/// changed.
+fn concat(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [EvaluateResult::List(a), EvaluateResult::List(b)] => Ok(EvaluateResult::list([a.borrow().clone(), b.borrow().clone()].concat())),
+        _ => bail!("concat expects two lists"),
+    }
+}
+
+/// `push(list, x)`: appends `x` to `list` in place, so every variable holding the list sees it.
+/// Returns nil.
+fn push(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [EvaluateResult::List(list), value] => {
+            list.borrow_mut().push(value.clone());
+            Ok(EvaluateResult::Nil)
+        }
+        _ => bail!("push expects a list as its first argument"),
+    }
+}
+
+/// `pop(list)`: removes the last element of `list` in place and returns it, or nil when the list
+/// is empty.
+fn pop(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [EvaluateResult::List(list)] => Ok(list.borrow_mut().pop().unwrap_or(EvaluateResult::Nil)),
+        _ => bail!("pop expects a list"),
+    }
+}
+
+/// `map()`: a new, empty map. `map(list, f)`: a new list of `f(element)` for each element of
+/// `list`, in order; the first error from `f` stops the mapping.
+fn map(
+    env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [] => Ok(EvaluateResult::map(Vec::new())),
+        [EvaluateResult::List(list), EvaluateResult::NativeFunction(f)] => {
+            let elements = list.borrow().clone();
+            let mapped = elements
+                .into_iter()
+                .map(|element| f.call(env, &[element]))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(EvaluateResult::list(mapped))
+        }
+        _ => bail!("map expects no arguments or a list and a function"),
+    }
+}
+
+/// `filter(list, predicate)`: a new list of the elements of `list`, in order, for which
+/// `predicate(element)` holds as a condition.
+fn filter(
+    env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let [EvaluateResult::List(list), EvaluateResult::NativeFunction(predicate)] = arguments else {
+        bail!("filter expects a list and a function")
+    };
+    let elements = list.borrow().clone();
+    let mut kept = Vec::new();
+    for element in elements {
+        let verdict = predicate.call(env, std::slice::from_ref(&element))?;
+        if env.condition(&verdict)? {
+            kept.push(element);
+        }
+    }
+    Ok(EvaluateResult::list(kept))
+}
+
+/// `range(start, end)` or `range(start, end, step)`: the integers from `start` up to, but
+/// excluding, `end`, `step` apart (1 by default), so `range(0, 10, 2)` is `[0, 2, 4, 6, 8]`. A
+/// negative step counts down, with `end` still excluded: `range(3, 0, -1)` is `[3, 2, 1]`. A range
+/// running the other way from its step is empty.
+fn range(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let (start, end, step) = match arguments {
+        [EvaluateResult::Number(Number::Integer(start)), EvaluateResult::Number(Number::Integer(end))] => (*start, *end, 1),
+        [EvaluateResult::Number(Number::Integer(start)), EvaluateResult::Number(Number::Integer(end)), EvaluateResult::Number(Number::Integer(step))] => {
+            (*start, *end, *step)
+        }
+        _ => bail!("range expects two or three integers"),
+    };
+    if step == 0 {
+        bail!("range step must not be zero")
+    }
+    let len = ((i128::from(end) - i128::from(start)) / i128::from(step)).max(0);
+    if len > MAX_RANGE_LEN as i128 {
+        bail!("range would have more than {} elements", MAX_RANGE_LEN)
+    }
+    let mut elements = Vec::new();
+    let mut i = Some(start);
+    while let Some(current) = i.filter(|&i| if step > 0 { i < end } else { i > end }) {
+        elements.push(EvaluateResult::Number(Number::Integer(current)));
+        i = current.checked_add(step);
+    }
+    Ok(EvaluateResult::list(elements))
+}
+
+/// `set(map, key, value)`: stores `value` under `key` in place. A new key goes after all existing
+/// ones; an existing key keeps its position. Returns nil.
+fn set(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let [EvaluateResult::Map(entries), key, value] = arguments else {
+        bail!("set expects a map as its first argument")
+    };
+    let key = MapKey::new(key)?;
+    let mut entries = entries.borrow_mut();
+    match entries.iter_mut().find(|(existing, _)| *existing == key) {
+        Some((_, existing)) => *existing = value.clone(),
+        None => entries.push((key, value.clone())),
+    }
+    Ok(EvaluateResult::Nil)
+}
+
+/// `get(map, key)`: the value stored under `key`, or nil when there is none.
+fn get(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let [EvaluateResult::Map(entries), key] = arguments else {
+        bail!("get expects a map as its first argument")
+    };
+    Ok(map_get(&entries.borrow(), &MapKey::new(key)?))
+}
+
+/// `keys(map)`: a list of the map's keys in insertion order.
+fn keys(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [EvaluateResult::Map(entries)] => Ok(EvaluateResult::list(entries.borrow().iter().map(|(key, _)| key.to_value()).collect())),
+        _ => bail!("keys expects a map"),
+    }
+}
+
+/// `write(x)`: like the `print` statement, but without the trailing newline, for building up a
+/// line piece by piece. Returns nil.
+fn write(
+    env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    write!(env.output(), "{}", arguments[0])?;
+    Ok(EvaluateResult::Nil)
+}
+
+/// `sprint(args...)`: the text `print` would write for each argument, joined by single spaces and
+/// without the trailing newline. Nothing is written to the output.
+fn sprint(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let parts = arguments.iter().map(EvaluateResult::to_string).collect::<Vec<String>>();
+    Ok(EvaluateResult::String(parts.join(" ")))
+}
+
+/// `bool(x)`: the truthiness of `x` as a boolean. Only `nil` and `false` are falsy, so `bool(0)`
+/// and `bool("")` are both true.
+fn boolean(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::Boolean(arguments[0].is_truthy()))
+}
+
+/// `isNil(x)`: whether `x` is `nil`.
+fn is_nil(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::Boolean(matches!(arguments[0], EvaluateResult::Nil)))
+}
+
+/// `isNumber(x)`: whether `x` is a number, integer or float.
+fn is_number(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::Boolean(matches!(arguments[0], EvaluateResult::Number(_))))
+}
+
+/// `isString(x)`: whether `x` is a string.
+fn is_string(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::Boolean(matches!(arguments[0], EvaluateResult::String(_))))
+}
+
+/// `isBool(x)`: whether `x` is `true` or `false`. Unlike `bool(x)`, this doesn't look at truthiness.
+fn is_bool(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    Ok(EvaluateResult::Boolean(matches!(arguments[0], EvaluateResult::Boolean(_))))
+}
+
+/// `assertEq(actual, expected)`: fails with both values' `repr` unless they are equal under `==`.
+/// Returns nil.
+fn assert_eq(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let [actual, expected] = arguments else {
+        bail!("assertEq expects two arguments")
+    };
+    if actual != expected {
+        bail!("assertEq failed: {} != {}", actual.repr(), expected.repr())
+    }
+    Ok(EvaluateResult::Nil)
+}
+
+/// `format(template, args...)`: replaces each `{}` in `template` with the next argument. `{{` and
+/// `}}` stand for literal braces. A placeholder may carry a spec after a colon: `{:8}` pads to a
+/// width of 8 (numbers on the left, anything else on the right), `{:08}` pads a number with
+/// zeros instead, and `{:.2}` prints a number with two decimals, as in `{:08.3}`.
+fn format(
+    _env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [EvaluateResult::String(template), arguments @ ..] => Ok(EvaluateResult::String(format_template(template, arguments)?)),
+        _ => bail!("format expects a template string as its first argument"),
+    }
+}
+
+/// `printf(template, args...)`: prints `format(template, args...)` followed by a newline, like the
+/// `print` statement. Returns nil.
+fn printf(
+    env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    match arguments {
+        [EvaluateResult::String(template), arguments @ ..] => {
+            let text = format_template(template, arguments)?;
+            writeln!(env.output(), "{}", text)?;
+            Ok(EvaluateResult::Nil)
+        }
+        _ => bail!("printf expects a template string as its first argument"),
+    }
+}
+
+/// `sort(list)` or `sort(list, cmp)`: a sorted copy of `list`. Without a comparator elements are
+/// ordered by `EvaluateResult::compare`; `cmp(a, b)` must return a number that is negative, zero
+/// or positive when `a` sorts before, with or after `b`.
+fn sort(
+    env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let (list, comparator) = match arguments {
+        [EvaluateResult::List(list)] => (list, None),
+        [EvaluateResult::List(list), EvaluateResult::NativeFunction(comparator)] => (list, Some(comparator)),
+        _ => bail!("sort expects a list and an optional comparator function"),
+    };
+    let mut sorted = list.borrow().clone();
+    let mut error = None;
+    sorted.sort_by(|a, b| {
+        if error.is_some() {
+            return Ordering::Equal;
+        }
+        let ordering = match comparator {
+            Some(comparator) => comparator.call(env, &[a.clone(), b.clone()]).and_then(|result| match result {
+                EvaluateResult::Number(number) => Ok(number.as_f64().partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                _ => bail!("sort comparator must return a number, got {}", result),
+            }),
+            None => a.compare(b),
+        };
+        ordering.unwrap_or_else(|e| {
+            error = Some(e);
+            Ordering::Equal
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(EvaluateResult::list(sorted)),
+    }
+}
+
+/// `reduce(list, initial, f)`: folds `list` from the left, calling `f(accumulator, element)` for
+/// each element and starting from `initial`. An empty list gives `initial`; the first error from
+/// `f` stops the fold.
+fn reduce(
+    env: &mut Environment,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<EvaluateResult> {
+    let [EvaluateResult::List(list), initial, EvaluateResult::NativeFunction(f)] = arguments else {
+        bail!("reduce expects a list, an initial value and a function")
+    };
+    let elements = list.borrow().clone();
+    elements
+        .into_iter()
+        .try_fold(initial.clone(), |accumulator, element| f.call(env, &[accumulator, element]))
+}
+
+fn format_template(
+    template: &str,
+    arguments: &[EvaluateResult],
+) -> anyhow::Result<String> {
+    let mut result = String::new();
+    let mut placeholders = 0;
+    let mut iter = template.chars().peekable();
+    while let Some(c) = iter.next() {
+        match (c, iter.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                iter.next();
+                result.push(c);
+            }
+            ('{', Some('}')) => {
+                iter.next();
+                if let Some(argument) = arguments.get(placeholders) {
+                    result.push_str(&argument.to_string());
+                }
+                placeholders += 1;
+            }
+            ('{', Some(':')) => {
+                iter.next();
+                let mut spec = String::new();
+                loop {
+                    match iter.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => bail!("format has an unmatched '{{'; use '{{{{' for a literal brace"),
+                    }
+                }
+                let spec = Spec::parse(&spec)?;
+                if let Some(argument) = arguments.get(placeholders) {
+                    result.push_str(&spec.apply(argument)?);
+                }
+                placeholders += 1;
+            }
+            ('{', _) | ('}', _) => bail!("format has an unmatched '{}'; use '{}{}' for a literal brace", c, c, c),
+            _ => result.push(c),
+        }
+    }
+    if placeholders != arguments.len() {
+        bail!("format has {} placeholders but got {} arguments", placeholders, arguments.len())
+    }
+    Ok(result)
+}
+
+/// The part of a `format` placeholder after the colon: `0`? width? ( `.` precision )?.
+struct Spec {
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> anyhow::Result<Spec> {
+        let invalid = || anyhow::anyhow!("invalid format spec '{{:{}}}'", spec);
+        let (width, precision) = match spec.split_once('.') {
+            Some((width, precision)) => (width, Some(precision.parse::<usize>().map_err(|_| invalid())?)),
+            None => (spec, None),
+        };
+        if !width.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        Ok(Spec {
+            zero: width.starts_with('0'),
+            width: if width.is_empty() { 0 } else { width.parse().map_err(|_| invalid())? },
+            precision,
+        })
+    }
+
+    fn apply(
+        &self,
+        value: &EvaluateResult,
+    ) -> anyhow::Result<String> {
+        let EvaluateResult::Number(number) = value else {
+            if self.zero || self.precision.is_some() {
+                bail!("format spec needs a number, got {}", value.type_name())
+            }
+            return Ok(format!("{:<width$}", value.to_string(), width = self.width));
+        };
+        let text = match self.precision {
+            Some(precision) => format!("{:.*}", precision, number.as_f64()),
+            None => number.to_string(),
+        };
+        if !self.zero {
+            return Ok(format!("{:>width$}", text, width = self.width));
+        }
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        Ok(format!("{}{:0>width$}", sign, digits, width = self.width.saturating_sub(sign.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+
+    use crate::{evaluating::EvaluateResult, interpreting::Interpreter, run, token::Number};
+
+    #[test]
+    fn contains_index_of() {
+        assert_eq!(run("contains(\"hello\", \"ell\");").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(run("contains(\"hello\", \"xyz\");").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(run("contains(\"hello\", \"\");").unwrap(), EvaluateResult::Boolean(true));
+
+        assert_eq!(run("indexOf(\"héllo\", \"llo\");").unwrap(), EvaluateResult::Number(Number::Integer(2)));
+        assert_eq!(run("indexOf(\"hello\", \"xyz\");").unwrap(), EvaluateResult::Number(Number::Integer(-1)));
+        assert_eq!(run("indexOf(\"hello\", \"\");").unwrap(), EvaluateResult::Number(Number::Integer(0)));
+
+        let err = run("indexOf(\"hello\", 1);").unwrap_err();
+        assert_eq!(err.to_string(), "indexOf expects two strings");
+    }
+
+    #[test]
+    fn globals() {
+        let EvaluateResult::List(names) = run("var alpha = 1;\nvar beta = 2;\nglobals();").unwrap() else {
+            panic!("expected a list")
+        };
+        let names = names.borrow();
+        assert!(names.contains(&EvaluateResult::String("alpha".to_string())));
+        assert!(names.contains(&EvaluateResult::String("beta".to_string())));
+        assert!(!names.contains(&EvaluateResult::String("gamma".to_string())));
+    }
+
+    #[test]
+    fn format() {
+        let result = run("var a = 1;\nvar b = 2;\nformat(\"{} + {} = {}\", a, b, a + b);").unwrap();
+        assert_eq!(result, EvaluateResult::String("1 + 2 = 3".to_string()));
+        let result = run("format(\"{{{}}}\", \"x\");").unwrap();
+        assert_eq!(result, EvaluateResult::String("{x}".to_string()));
+
+        let err = run("format(\"{} and {}\", 1);").unwrap_err();
+        assert_eq!(err.to_string(), "format has 2 placeholders but got 1 arguments");
+    }
+
+    #[test]
+    fn format_specs() {
+        let format = |source: &str| match run(source).unwrap() {
+            EvaluateResult::String(s) => s,
+            other => panic!("expected a string, got {}", other),
+        };
+        assert_eq!(format("format(\"{:.2}\", 3.14159);"), "3.14");
+        assert_eq!(format("format(\"{:05}|{:5}|{:3}\", 42, -7, \"ab\");"), "00042|   -7|ab ");
+        assert_eq!(format("format(\"{:08.3}\", -2.5);"), "-002.500");
+        assert_eq!(format("format(\"{:.1}\", 2);"), "2.0");
+
+        let err = run("format(\"{:5\", 1);").unwrap_err();
+        assert_eq!(err.to_string(), "format has an unmatched '{'; use '{{' for a literal brace");
+        let err = run("format(\"{:x}\", 1);").unwrap_err();
+        assert_eq!(err.to_string(), "invalid format spec '{:x}'");
+        let err = run("format(\"{:.2}\", \"a\");").unwrap_err();
+        assert_eq!(err.to_string(), "format spec needs a number, got string");
+    }
+
+    #[test]
+    fn sort() {
+        let result = run("sort([3, 1.5, 2, -4]);").unwrap();
+        assert_eq!(run("[-4, 1.5, 2, 3];").unwrap(), result);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("descending", 2, |arguments| match arguments {
+            [EvaluateResult::Number(a), EvaluateResult::Number(b)] => Ok(EvaluateResult::Number(Number::Float(b.as_f64() - a.as_f64()))),
+            _ => bail!("descending expects two numbers"),
+        });
+        let result = interpreter.eval_source("sort([1, 3, 2], descending);").unwrap();
+        assert_eq!(run("[3, 2, 1];").unwrap(), result);
+
+        let err = run("sort([1, \"a\"]);").unwrap_err();
+        assert_eq!(err.to_string(), "cannot compare string and number");
+    }
+
+    #[test]
+    fn reduce() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("add", 2, |arguments| match arguments {
+            [EvaluateResult::Number(a), EvaluateResult::Number(b)] => Ok(EvaluateResult::Number(*a + *b)),
+            _ => bail!("add expects two numbers"),
+        });
+        assert_eq!(
+            interpreter.eval_source("reduce([1, 2, 3], 0, add);").unwrap(),
+            EvaluateResult::Number(Number::Integer(6))
+        );
+        assert_eq!(
+            interpreter.eval_source("reduce([], 7, add);").unwrap(),
+            EvaluateResult::Number(Number::Integer(7))
+        );
+        let err = interpreter.eval_source("reduce([1, \"2\"], 0, add);").unwrap_err();
+        assert_eq!(err.to_string(), "add expects two numbers");
+
+        // The accumulator comes first and elements are visited left to right.
+        assert_eq!(
+            run("reduce([1, 2, 3], 0, sprint);").unwrap(),
+            EvaluateResult::String("0 1 2 3".to_string())
+        );
+        let err = run("reduce([1], 0, 1);").unwrap_err();
+        assert_eq!(err.to_string(), "reduce expects a list, an initial value and a function");
+    }
+
+    #[test]
+    fn map_filter() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("double", 1, |arguments| match arguments {
+            [EvaluateResult::Number(n)] => Ok(EvaluateResult::Number(*n * Number::Integer(2))),
+            _ => bail!("double expects a number"),
+        });
+        interpreter.register_fn("isEven", 1, |arguments| match arguments {
+            [EvaluateResult::Number(Number::Integer(n))] => Ok(EvaluateResult::Boolean(n % 2 == 0)),
+            _ => bail!("isEven expects an integer"),
+        });
+        let list = |source: &str| run(source).unwrap();
+        assert_eq!(interpreter.eval_source("map([1, 2, 3], double);").unwrap(), list("[2, 4, 6];"));
+        assert_eq!(interpreter.eval_source("filter([1, 2, 3, 4, 6], isEven);").unwrap(), list("[2, 4, 6];"));
+        assert_eq!(interpreter.eval_source("map([], double);").unwrap(), list("[];"));
+
+        let err = interpreter.eval_source("map([1, \"x\"], double);").unwrap_err();
+        assert_eq!(err.to_string(), "double expects a number");
+        let err = interpreter.eval_source("filter([1.5], isEven);").unwrap_err();
+        assert_eq!(err.to_string(), "isEven expects an integer");
+
+        // Without arguments `map` still makes an empty map.
+        assert_eq!(run("map();").unwrap().type_name(), "map");
+        assert_eq!(run("filter([1, nil, 0, false], sprint);").unwrap().to_string(), "[1, nil, 0, false]");
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(run("range(0, 5);").unwrap(), run("[0, 1, 2, 3, 4];").unwrap());
+        assert_eq!(run("range(0, 10, 2);").unwrap(), run("[0, 2, 4, 6, 8];").unwrap());
+        assert_eq!(run("range(0, 9, 3);").unwrap(), run("[0, 3, 6];").unwrap());
+        assert_eq!(run("range(5, 0, -2);").unwrap(), run("[5, 3, 1];").unwrap());
+        assert_eq!(run("range(0, 5, -1);").unwrap(), run("[];").unwrap());
+        assert_eq!(
+            run("range(9223372036854775806, 9223372036854775807, 5);").unwrap(),
+            run("[9223372036854775806];").unwrap()
+        );
+
+        assert_eq!(run("range(0, 5, 0);").unwrap_err().to_string(), "range step must not be zero");
+        assert_eq!(
+            run("range(0, 1000000000000);").unwrap_err().to_string(),
+            "range would have more than 16777216 elements"
+        );
+        // Only the number of elements counts, not the distance between the bounds.
+        assert_eq!(
+            run("range(0, 3000000000000, 1000000000000);").unwrap(),
+            run("[0, 1000000000000, 2000000000000];").unwrap()
+        );
+        assert_eq!(run("range(0, 1.5);").unwrap_err().to_string(), "range expects two or three integers");
+    }
+
+    #[test]
+    fn trim_upper_lower() {
+        assert_eq!(run("trim(\"  hi  \");").unwrap(), EvaluateResult::String("hi".to_string()));
+        assert_eq!(run("upper(\"Hi\");").unwrap(), EvaluateResult::String("HI".to_string()));
+        assert_eq!(run("lower(\"Hi\");").unwrap(), EvaluateResult::String("hi".to_string()));
+
+        let err = run("upper(1);").unwrap_err();
+        assert_eq!(err.to_string(), "upper expects a string, got number");
+    }
+
+    #[test]
+    fn concat_push_pop() {
+        assert_eq!(run("concat([1, 2], [3]);").unwrap(), run("[1, 2, 3];").unwrap());
+        assert_eq!(run("var a = [1];\nconcat(a, a);\na;").unwrap(), run("[1];").unwrap());
+
+        assert_eq!(run("var a = [1];\nvar b = a;\npush(b, 2);\na;").unwrap(), run("[1, 2];").unwrap());
+        assert_eq!(run("var a = [1, 2];\npop(a);").unwrap(), EvaluateResult::Number(Number::Integer(2)));
+        assert_eq!(run("var a = [1, 2];\npop(a);\na;").unwrap(), run("[1];").unwrap());
+        assert_eq!(run("pop([]);").unwrap(), EvaluateResult::Nil);
+    }
+
+    #[test]
+    fn assert_eq() {
+        let err = Interpreter::new().eval_file("tests/assert_eq.lox").unwrap_err();
+        assert_eq!(err.to_string(), "assertEq failed: [1, \"2\"] != [1, 2]");
+    }
+
+    #[test]
+    fn bool() {
+        assert_eq!(run("bool(nil);").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(run("bool(false);").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(run("bool(0);").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(run("bool(\"\");").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(run("bool([]);").unwrap(), EvaluateResult::Boolean(true));
+    }
+
+    #[test]
+    fn maps() {
+        let source =
+            "var m = map();\nset(m, \"zebra\", 1);\nset(m, 2, \"two\");\nset(m, \"apple\", [3]);\nset(m, nil, true);\nset(m, \"zebra\", 0);\n";
+        // Entries keep insertion order however the keys would hash or sort.
+        for _ in 0..10 {
+            assert_eq!(
+                run(&format!("{}m;", source)).unwrap().repr(),
+                "{\"zebra\": 0, 2: \"two\", \"apple\": [3], nil: true}"
+            );
+        }
+        assert_eq!(
+            run(&format!("{}sprint(m);", source)).unwrap(),
+            EvaluateResult::String("{zebra: 0, 2: two, apple: [3], nil: true}".to_string())
+        );
+        assert_eq!(run(&format!("{}keys(m);", source)).unwrap().to_string(), "[zebra, 2, apple, nil]");
+        assert_eq!(run(&format!("{}get(m, 2);", source)).unwrap(), EvaluateResult::String("two".to_string()));
+        assert_eq!(run(&format!("{}get(m, 2.0);", source)).unwrap(), EvaluateResult::Nil);
+        assert_eq!(run("set(map(), [], 1);").unwrap_err().to_string(), "list can't be used as a map key");
+    }
+
+    #[test]
+    fn type_predicates() {
+        for (source, expected) in [
+            ("isNil(nil);", true),
+            ("isNil(false);", false),
+            ("isNumber(2.5);", true),
+            ("isNumber(\"2\");", false),
+            ("isString(\"\");", true),
+            ("isString([]);", false),
+            ("isBool(false);", true),
+            ("isBool(0);", false),
+        ] {
+            assert_eq!(run(source).unwrap(), EvaluateResult::Boolean(expected), "{}", source);
+        }
+    }
+}
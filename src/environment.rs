@@ -0,0 +1,694 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    ast::AstNode,
+    builtins,
+    evaluating::{BinaryOperatorFn, EvaluateResult},
+};
+
+/// Runtime state of an evaluation: the chain of variable scopes plus the interpreter settings.
+pub struct Environment {
+    scope: Scope,
+    /// Where names missing from every scope of this environment are looked up: see `new_child`.
+    parent: Option<Rc<RefCell<Environment>>>,
+    strict_conditions: bool,
+    define_on_assign: bool,
+    output: Box<dyn Write>,
+    step_budget: Option<usize>,
+    steps: usize,
+    operators: HashMap<String, Box<BinaryOperatorFn>>,
+    current_file: Option<PathBuf>,
+    imported: HashSet<PathBuf>,
+    trace: bool,
+    integer_only: bool,
+    saturating: bool,
+    /// The value of a `return` on its way to the program boundary.
+    return_value: Option<EvaluateResult>,
+    memo: Option<HashMap<*const AstNode, (AstNode, EvaluateResult)>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        let mut env = Self::empty();
+        builtins::define_builtins(&mut env);
+        env
+    }
+}
+
+impl Environment {
+    fn empty() -> Self {
+        Self {
+            scope: Scope::default(),
+            parent: None,
+            strict_conditions: false,
+            define_on_assign: false,
+            output: Box::new(std::io::stdout()),
+            step_budget: None,
+            steps: 0,
+            operators: HashMap::new(),
+            current_file: None,
+            imported: HashSet::new(),
+            trace: false,
+            integer_only: false,
+            saturating: false,
+            return_value: None,
+            memo: None,
+        }
+    }
+
+    /// A fresh environment at the global scope, with the builtins defined. Same as `default`.
+    pub fn new_global() -> Self {
+        Self::default()
+    }
+
+    /// A fresh environment, with default settings and no builtins of its own, whose reads and
+    /// assignments of names it doesn't define go on to `parent`. A program run in the child
+    /// declares its globals in the child, so several children can share one parent, such as a
+    /// restricted global for sandboxed programs, without seeing each other's variables.
+    pub fn new_child(parent: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            parent: Some(parent),
+            ..Self::empty()
+        }
+    }
+
+    /// The environment given to `new_child`, if any.
+    pub fn parent(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.parent.as_ref()
+    }
+
+    pub fn with_output(
+        mut self,
+        output: impl Write + 'static,
+    ) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Caps how many nodes may be evaluated, so untrusted programs can't loop forever. Unlimited
+    /// unless set.
+    pub fn with_step_budget(
+        mut self,
+        budget: usize,
+    ) -> Self {
+        self.step_budget = Some(budget);
+        self
+    }
+
+    /// Logs every evaluated node and its result to stderr.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = true;
+        self
+    }
+
+    pub fn trace(&self) -> bool {
+        self.trace
+    }
+
+    /// Caches the value of pure operator expressions (see `AstNode::is_pure`) by node identity, so
+    /// a constant subexpression inside a loop body is computed once. The cache lasts for one
+    /// program: see `forget_memoized`.
+    pub fn with_memoization(mut self) -> Self {
+        self.memo = Some(HashMap::new());
+        self
+    }
+
+    pub fn memoizing(&self) -> bool {
+        self.memo.is_some()
+    }
+
+    /// The cached value of `node`. A node freed during the run, such as one of an imported file,
+    /// can leave an entry behind at an address that is later reused, so the cached node must also
+    /// still match.
+    pub fn memoized(
+        &self,
+        node: &AstNode,
+    ) -> Option<EvaluateResult> {
+        match self.memo.as_ref()?.get(&(node as *const AstNode)) {
+            Some((cached, value)) if cached == node => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn memoize(
+        &mut self,
+        node: &AstNode,
+        value: EvaluateResult,
+    ) {
+        if let Some(memo) = &mut self.memo {
+            memo.insert(node as *const AstNode, (node.clone(), value));
+        }
+    }
+
+    /// Empties the cache. Called whenever a program starts or an imported one ends, so entries for
+    /// freed nodes don't pile up.
+    pub fn forget_memoized(&mut self) {
+        if let Some(memo) = &mut self.memo {
+            memo.clear();
+        }
+    }
+
+    pub fn count_step(&mut self) -> anyhow::Result<()> {
+        self.steps += 1;
+        match self.step_budget {
+            Some(budget) if self.steps > budget => bail!("execution budget exceeded"),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    /// Consulted before the built-in behavior of `operator`, replacing any earlier override.
+    pub fn define_operator(
+        &mut self,
+        operator: &str,
+        f: impl Fn(&EvaluateResult, &EvaluateResult) -> anyhow::Result<Option<EvaluateResult>> + 'static,
+    ) {
+        self.operators.insert(operator.to_string(), Box::new(f));
+    }
+
+    pub fn operator(
+        &self,
+        operator: &str,
+    ) -> Option<&BinaryOperatorFn> {
+        self.operators.get(operator).map(|f| f.as_ref())
+    }
+
+    /// `path` as written in an `import`, taken relative to the directory of the file being run,
+    /// or to the working directory for sources that didn't come from a file.
+    pub fn resolve_import(
+        &self,
+        path: &str,
+    ) -> PathBuf {
+        match self.current_file.as_deref().and_then(Path::parent) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// Records that the canonical `path` has been run, returning false if it already had been.
+    pub fn mark_imported(
+        &mut self,
+        path: PathBuf,
+    ) -> bool {
+        self.imported.insert(path)
+    }
+
+    /// Sets the file whose statements are being evaluated, returning the previous one.
+    pub fn replace_current_file(
+        &mut self,
+        file: Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        std::mem::replace(&mut self.current_file, file)
+    }
+
+    /// In strict mode a condition must evaluate to a `Boolean`, so `if 5 print 1;` is an error
+    /// instead of running the branch. By default conditions use truthiness: only `nil` and
+    /// `false` are falsy.
+    pub fn with_strict_conditions(mut self) -> Self {
+        self.strict_conditions = true;
+        self
+    }
+
+    /// Integer-only dialect: an arithmetic result that would be a float, such as `2 ** -1`, is an
+    /// error. Division between integers already truncates, so `7 / 2` is `3` either way. Pair it
+    /// with `lexing_str_integer_only` so float literals are rejected too.
+    pub fn with_integer_only(mut self) -> Self {
+        self.integer_only = true;
+        self
+    }
+
+    pub fn integer_only(&self) -> bool {
+        self.integer_only
+    }
+
+    /// Saturating mode: integer `+`, `-`, `*`, `**` and negation clamp at `i64::MIN` and
+    /// `i64::MAX` instead of overflowing. See the saturating methods of `Number`.
+    pub fn with_saturating(mut self) -> Self {
+        self.saturating = true;
+        self
+    }
+
+    pub fn saturating(&self) -> bool {
+        self.saturating
+    }
+
+    pub(crate) fn set_return_value(
+        &mut self,
+        value: EvaluateResult,
+    ) {
+        self.return_value = Some(value);
+    }
+
+    pub(crate) fn take_return_value(&mut self) -> EvaluateResult {
+        self.return_value.take().unwrap_or(EvaluateResult::Nil)
+    }
+
+    /// Assigning to an undeclared variable defines it as a global instead of failing.
+    pub fn with_define_on_assign(mut self) -> Self {
+        self.define_on_assign = true;
+        self
+    }
+
+    pub fn condition(
+        &self,
+        value: &EvaluateResult,
+    ) -> anyhow::Result<bool> {
+        if self.strict_conditions {
+            value.to_bool_strict()
+        } else {
+            Ok(value.is_truthy())
+        }
+    }
+
+    pub fn define(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) {
+        self.scope.vars.insert(name.to_string(), Binding { value, constant: false });
+    }
+
+    /// Like `define`, but any later assignment to `name` fails.
+    pub fn define_constant(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) {
+        self.scope.vars.insert(name.to_string(), Binding { value, constant: true });
+    }
+
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<EvaluateResult> {
+        match &self.parent {
+            Some(parent) if !self.scope.defines(name) => parent.borrow().get(name),
+            _ => self.scope.get(name),
+        }
+    }
+
+    pub fn assign(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) -> anyhow::Result<()> {
+        match &self.parent {
+            Some(parent) if !self.scope.defines(name) => parent.borrow_mut().assign(name, value),
+            _ => self.scope.assign(name, value),
+        }
+    }
+
+    /// Looks `name` up exactly `depth` scopes above the current one, as annotated by the resolver.
+    pub fn get_at(
+        &self,
+        depth: usize,
+        name: &str,
+    ) -> anyhow::Result<EvaluateResult> {
+        let scope = self.scope.ancestor(depth).context("Invalid scope depth")?;
+        match scope.vars.get(name) {
+            Some(binding) => Ok(binding.value.clone()),
+            None => bail!("Undefined variable {}", name),
+        }
+    }
+
+    /// Assigns `name` exactly `depth` scopes above the current one, as annotated by the resolver.
+    pub fn assign_at(
+        &mut self,
+        depth: usize,
+        name: &str,
+        value: EvaluateResult,
+    ) -> anyhow::Result<()> {
+        let scope = self.scope.ancestor_mut(depth).context("Invalid scope depth")?;
+        match scope.vars.get_mut(name) {
+            Some(binding) => binding.assign(name, value),
+            None => bail!("Undefined variable {}", name),
+        }
+    }
+
+    pub fn get_global(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<EvaluateResult> {
+        match &self.parent {
+            Some(parent) if !self.scope.global().defines(name) => parent.borrow().get(name),
+            _ => self.scope.global().get(name),
+        }
+    }
+
+    pub fn define_global(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) {
+        self.scope.global_mut().vars.insert(name.to_string(), Binding { value, constant: false });
+    }
+
+    pub fn assign_global(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) -> anyhow::Result<()> {
+        if let Some(parent) = self.parent_defining(name) {
+            return parent.borrow_mut().assign(name, value);
+        }
+        let global = self.scope.global_mut();
+        if self.define_on_assign && !global.vars.contains_key(name) {
+            global.vars.insert(name.to_string(), Binding { value, constant: false });
+            return Ok(());
+        }
+        global.assign(name, value)
+    }
+
+    /// Assigns the global `name`, defining it first if there is no such global yet. Like any
+    /// assignment, fails on a constant.
+    pub fn bind_global(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) -> anyhow::Result<()> {
+        if let Some(parent) = self.parent_defining(name) {
+            return parent.borrow_mut().assign(name, value);
+        }
+        let global = self.scope.global_mut();
+        match global.vars.get_mut(name) {
+            Some(binding) => binding.assign(name, value),
+            None => {
+                global.vars.insert(name.to_string(), Binding { value, constant: false });
+                Ok(())
+            }
+        }
+    }
+
+    /// The parent environment, when it rather than this one's global scope defines `name`.
+    fn parent_defining(
+        &self,
+        name: &str,
+    ) -> Option<Rc<RefCell<Environment>>> {
+        let parent = self.parent.as_ref()?;
+        if self.scope.global().defines(name) || parent.borrow().get(name).is_err() {
+            return None;
+        }
+        Some(parent.clone())
+    }
+
+    /// Names defined in the global scope, sorted.
+    pub fn global_names(&self) -> Vec<String> {
+        let mut names = self.scope.global().vars.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    /// Enters a new, empty child of the current scope. `define` then binds in the child, shadowing
+    /// any outer variable of the same name, while lookups fall through to the parents.
+    pub fn forward(&mut self) {
+        self.scope = std::mem::take(&mut self.scope).forward();
+    }
+
+    /// Leaves the current scope, dropping its variables, and returns to its parent. At the global
+    /// scope this does nothing.
+    pub fn expire(&mut self) {
+        self.scope = std::mem::take(&mut self.scope).expire();
+    }
+
+    /// How many scopes the current one is nested inside: 0 at the global scope.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut scope = &self.scope;
+        while let Some(parent) = &scope.parent {
+            depth += 1;
+            scope = parent;
+        }
+        depth
+    }
+
+    /// Names defined directly in the current scope, sorted, without those of its parents.
+    pub fn local_names(&self) -> Vec<String> {
+        self.scope().local_names()
+    }
+
+    /// The current scope, from which `ScopeRef::parent` walks the chain out to the global scope.
+    pub fn scope(&self) -> ScopeRef<'_> {
+        ScopeRef { scope: &self.scope }
+    }
+}
+
+/// A read-only view of one scope in an environment's chain.
+#[derive(Clone, Copy)]
+pub struct ScopeRef<'a> {
+    scope: &'a Scope,
+}
+
+impl<'a> ScopeRef<'a> {
+    /// The value bound to `name` in this scope itself, ignoring its parents.
+    pub fn get_local(
+        &self,
+        name: &str,
+    ) -> Option<EvaluateResult> {
+        self.scope.vars.get(name).map(|binding| binding.value.clone())
+    }
+
+    /// Looks `name` up in this scope, then in its parents.
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<EvaluateResult> {
+        self.scope.get(name)
+    }
+
+    /// Names defined directly in this scope, sorted.
+    pub fn local_names(&self) -> Vec<String> {
+        let mut names = self.scope.vars.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    pub fn parent(&self) -> Option<ScopeRef<'a>> {
+        self.scope.parent.as_deref().map(|scope| ScopeRef { scope })
+    }
+}
+
+struct Binding {
+    value: EvaluateResult,
+    constant: bool,
+}
+
+impl Binding {
+    fn assign(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) -> anyhow::Result<()> {
+        if self.constant {
+            bail!("cannot reassign to constant {}", name)
+        }
+        self.value = value;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Scope {
+    parent: Option<Box<Scope>>,
+    vars: HashMap<String, Binding>,
+}
+
+impl Scope {
+    /// Whether `name` is bound in this scope or one of its parents.
+    fn defines(
+        &self,
+        name: &str,
+    ) -> bool {
+        self.vars.contains_key(name) || self.parent.as_ref().is_some_and(|parent| parent.defines(name))
+    }
+
+    fn get(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<EvaluateResult> {
+        match self.vars.get(name) {
+            Some(binding) => Ok(binding.value.clone()),
+            None => match &self.parent {
+                Some(parent) => parent.get(name),
+                None => bail!("Undefined variable {}", name),
+            },
+        }
+    }
+
+    fn assign(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) -> anyhow::Result<()> {
+        if let Some(binding) = self.vars.get_mut(name) {
+            return binding.assign(name, value);
+        }
+        match &mut self.parent {
+            Some(parent) => parent.assign(name, value),
+            None => bail!("Undefined variable {}", name),
+        }
+    }
+
+    fn ancestor(
+        &self,
+        depth: usize,
+    ) -> Option<&Scope> {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.parent.as_deref()?;
+        }
+        Some(scope)
+    }
+
+    fn ancestor_mut(
+        &mut self,
+        depth: usize,
+    ) -> Option<&mut Scope> {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.parent.as_deref_mut()?;
+        }
+        Some(scope)
+    }
+
+    fn global(&self) -> &Scope {
+        match &self.parent {
+            Some(parent) => parent.global(),
+            None => self,
+        }
+    }
+
+    fn global_mut(&mut self) -> &mut Scope {
+        let mut scope = self;
+        while scope.parent.is_some() {
+            scope = scope.parent.as_deref_mut().unwrap();
+        }
+        scope
+    }
+
+    fn expire(self) -> Self {
+        match self.parent {
+            Some(parent) => *parent,
+            None => self,
+        }
+    }
+
+    fn forward(self) -> Self {
+        let parent = Some(Box::new(self));
+        let vars = HashMap::new();
+        Self { parent, vars }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::Environment;
+    use crate::{
+        ast::AstNode,
+        evaluating::{evaluate_program, EvaluateResult},
+        interpreting::{parse, ParseOptions},
+        token::Number,
+    };
+
+    #[test]
+    fn child_scopes() {
+        let number = |i| EvaluateResult::Number(Number::Integer(i));
+        let mut env = Environment::default();
+        env.define("shared", number(1));
+        env.define("shadowed", number(2));
+
+        env.forward();
+        assert_eq!(env.depth(), 1);
+        env.define("shadowed", number(20));
+        env.define("local", number(30));
+        assert_eq!(env.local_names(), vec!["local".to_string(), "shadowed".to_string()]);
+        assert_eq!(env.get("shared").unwrap(), number(1));
+        assert_eq!(env.get("shadowed").unwrap(), number(20));
+        assert_eq!(env.get_at(1, "shadowed").unwrap(), number(2));
+        env.assign("shared", number(10)).unwrap();
+
+        env.expire();
+        assert_eq!(env.depth(), 0);
+        assert_eq!(env.get("shared").unwrap(), number(10));
+        assert_eq!(env.get("shadowed").unwrap(), number(2));
+        assert!(env.get("local").is_err());
+        env.expire();
+        assert_eq!(env.depth(), 0);
+    }
+
+    #[test]
+    fn new_child() {
+        let number = |i| EvaluateResult::Number(Number::Integer(i));
+        let run = |source: &str, env: &mut Environment| evaluate_program(&parse(source, &ParseOptions::default()).unwrap(), env);
+        let parent = Rc::new(RefCell::new(Environment::new_global()));
+        parent.borrow_mut().define("limit", number(10));
+        assert!(parent.borrow().parent().is_none());
+
+        // Two programs share the parent but declare their own globals.
+        let mut first = Environment::new_child(parent.clone());
+        let mut second = Environment::new_child(parent.clone());
+        assert!(Rc::ptr_eq(first.parent().unwrap(), &parent));
+        assert_eq!(run("var a = 1; a = a + limit; a;", &mut first).unwrap(), number(11));
+        assert_eq!(run("upper(\"ok\");", &mut first).unwrap(), EvaluateResult::String("OK".to_string()));
+        assert_eq!(run("a;", &mut second).unwrap_err().to_string(), "Undefined variable a");
+        assert!(parent.borrow().get("a").is_err());
+
+        // Assigning a name only the parent defines writes through to it; a declaration shadows it.
+        assert_eq!(run("limit = limit * 2; limit;", &mut second).unwrap(), number(20));
+        assert_eq!(run("limit;", &mut first).unwrap(), number(20));
+        assert_eq!(run("var limit = 1; { limit = limit + 1; } limit;", &mut first).unwrap(), number(2));
+        assert_eq!(parent.borrow().get("limit").unwrap(), number(20));
+
+        // Block scopes inside the child can still be walked outwards.
+        first.forward();
+        first.define("local", number(30));
+        assert_eq!(first.scope().local_names(), vec!["local".to_string()]);
+        let global = first.scope().parent().unwrap();
+        assert_eq!(global.get_local("limit"), Some(number(2)));
+        assert_eq!(global.get_local("local"), None);
+        assert!(global.parent().is_none());
+    }
+
+    #[test]
+    fn memo_per_program() {
+        let mut env = Environment::default().with_memoization();
+        let first = parse("1 + 2;\n3 * 4;", &ParseOptions::default()).unwrap();
+        evaluate_program(&first, &mut env).unwrap();
+        assert_eq!(env.memo.as_ref().unwrap().len(), 2);
+        drop(first);
+
+        let second = parse("5 - 1;", &ParseOptions::default()).unwrap();
+        assert_eq!(evaluate_program(&second, &mut env).unwrap(), EvaluateResult::Number(Number::Integer(4)));
+        assert_eq!(env.memo.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn memo_checks_node() {
+        let mut env = Environment::default().with_memoization();
+        let mut program = parse("2 * 3;", &ParseOptions::default()).unwrap();
+        assert_eq!(evaluate_program(&program, &mut env).unwrap(), EvaluateResult::Number(Number::Integer(6)));
+
+        // A different node written over the same address must not see the old value.
+        let [AstNode::ExprStmt(expr)] = program.as_mut_slice() else {
+            unreachable!()
+        };
+        let Some(AstNode::ExprStmt(replacement)) = parse("5 - 1;", &ParseOptions::default()).unwrap().pop() else {
+            unreachable!()
+        };
+        assert!(env.memoized(expr).is_some());
+        **expr = *replacement;
+        assert_eq!(env.memoized(expr), None);
+    }
+}
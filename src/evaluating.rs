@@ -1,99 +1,922 @@
-use std::ops::Add;
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    fmt::{Debug, Display, Formatter},
+    ops::Add,
+    rc::Rc,
+};
 
-use crate::{ast::AstNode, token::Number};
+use anyhow::{bail, Context};
+
+use crate::{
+    ast::AstNode,
+    environment::Environment,
+    interpreting::{self, ParseOptions},
+    token::Number,
+};
+
+/// The longest string `"s" * n` may build, so a huge count fails cleanly instead of exhausting
+/// memory.
+const MAX_REPEAT_LEN: usize = 1 << 30;
+
+/// The most elements a range may build, for the same reason.
+pub(crate) const MAX_RANGE_LEN: usize = 1 << 24;
+
+/// Evaluates a single top-level statement against `env`. All state lives in `env`, so a caller can
+/// inspect or modify it between steps.
+pub fn step(
+    node: &AstNode,
+    env: &mut Environment,
+) -> anyhow::Result<EvaluateResult> {
+    node.evaluate(env)
+}
+
+/// Steps through every statement of `program`, returning the value of the last one, or the
+/// value of a `return` that ends the program early.
+pub fn evaluate_program(
+    program: &[AstNode],
+    env: &mut Environment,
+) -> anyhow::Result<EvaluateResult> {
+    evaluate_until_return(program, env).map(|(result, _)| result)
+}
+
+/// Runs a program parsed once against `env`, e.g. a fresh sandbox per request. Evaluation never
+/// mutates `program`: every variable, import and memoized result lives in `env`, and a memoized
+/// result is only reused for a node that still matches the one it was computed for, so separate
+/// environments never see each other's state.
+pub fn evaluate_in(
+    program: &[AstNode],
+    env: &mut Environment,
+) -> anyhow::Result<EvaluateResult> {
+    evaluate_program(program, env)
+}
+
+/// Like `evaluate_program`, also telling whether the program ended at a `return`.
+pub(crate) fn evaluate_until_return(
+    program: &[AstNode],
+    env: &mut Environment,
+) -> anyhow::Result<(EvaluateResult, bool)> {
+    env.forget_memoized();
+    let mut result = EvaluateResult::Nil;
+    for node in program {
+        result = match caught_return(step(node, env), env)? {
+            (value, true) => return Ok((value, true)),
+            (value, false) => value,
+        };
+    }
+    Ok((result, false))
+}
+
+/// Unwinds evaluation from a `return` statement up to the program boundary, where
+/// `caught_return` picks up the value the statement left in the environment. Values aren't
+/// `Send`, so they can't travel inside an `anyhow::Error` themselves.
+#[derive(Debug)]
+pub(crate) struct Return;
+
+impl Display for Return {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "return outside of a program")
+    }
+}
+
+impl std::error::Error for Return {}
+
+/// Unwinds evaluation from `break` or `continue` up to the loop it applies to: the innermost one
+/// without a label, otherwise the enclosing loop with that label.
+#[derive(Debug)]
+pub(crate) enum LoopSignal {
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+impl LoopSignal {
+    fn label(&self) -> Option<&str> {
+        match self {
+            LoopSignal::Break(label) | LoopSignal::Continue(label) => label.as_deref(),
+        }
+    }
+}
+
+impl Display for LoopSignal {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        let keyword = match self {
+            LoopSignal::Break(_) => "break",
+            LoopSignal::Continue(_) => "continue",
+        };
+        match self.label() {
+            Some(label) => write!(f, "'{} {}' outside of a loop labeled '{}'", keyword, label, label),
+            None => write!(f, "'{}' outside of a loop", keyword),
+        }
+    }
+}
+
+impl std::error::Error for LoopSignal {}
+
+/// Whether a loop labeled `label` goes on after its body gave `result`: a `continue` aimed at it
+/// goes on, a `break` aimed at it stops, and any other error propagates.
+fn keep_looping(
+    result: anyhow::Result<EvaluateResult>,
+    label: Option<&str>,
+) -> anyhow::Result<bool> {
+    let Err(err) = result else { return Ok(true) };
+    match err.downcast_ref::<LoopSignal>() {
+        Some(signal) if signal.label().is_none() || signal.label() == label => Ok(matches!(signal, LoopSignal::Continue(_))),
+        _ => Err(err),
+    }
+}
+
+/// Turns a `Return` unwinding through `result` into its value, flagged as returned.
+pub(crate) fn caught_return(
+    result: anyhow::Result<EvaluateResult>,
+    env: &mut Environment,
+) -> anyhow::Result<(EvaluateResult, bool)> {
+    match result {
+        Ok(value) => Ok((value, false)),
+        Err(err) if err.is::<Return>() => Ok((env.take_return_value(), true)),
+        Err(err) => Err(err),
+    }
+}
 
 impl AstNode {
-    pub fn evaluate(&self) -> EvaluateResult {
+    pub fn evaluate(
+        &self,
+        env: &mut Environment,
+    ) -> anyhow::Result<EvaluateResult> {
+        env.count_step()?;
+        let memoize = env.memoizing() && matches!(self, Self::Binary { .. } | Self::Unary { .. }) && self.is_pure();
+        if memoize {
+            if let Some(value) = env.memoized(self) {
+                return Ok(value);
+            }
+            let value = self.evaluate_traced(env)?;
+            env.memoize(self, value.clone());
+            return Ok(value);
+        }
+        self.evaluate_traced(env)
+    }
+
+    fn evaluate_traced(
+        &self,
+        env: &mut Environment,
+    ) -> anyhow::Result<EvaluateResult> {
+        if !env.trace() {
+            return self.evaluate_node(env);
+        }
+        let result = self.evaluate_node(env);
+        match &result {
+            Ok(value) => eprintln!("[trace] {} => {}", self, value.repr()),
+            Err(e) => eprintln!("[trace] {} => error: {}", self, e),
+        }
+        result
+    }
+
+    fn evaluate_node(
+        &self,
+        env: &mut Environment,
+    ) -> anyhow::Result<EvaluateResult> {
         match self {
-            Self::Boolean(v) => EvaluateResult::Boolean(*v),
-            Self::Number(v) => EvaluateResult::Number(*v),
-            Self::String(v) => EvaluateResult::String(v.clone()),
-            Self::Nil => EvaluateResult::Nil,
-            Self::Binary { .. } => self.evaluate_binary(),
-            Self::Unary { .. } => self.evaluate_unary(),
-            Self::Group(node) => node.evaluate(),
-            Self::Print(expr) => expr.evaluate(),
-            Self::Variable { value, .. } => {
-                if let Some(v) = value {
-                    v.evaluate()
-                } else {
-                    EvaluateResult::Nil
+            Self::Boolean(v) => Ok(EvaluateResult::Boolean(*v)),
+            Self::Number(v) => Ok(EvaluateResult::Number(*v)),
+            Self::String(v) => Ok(EvaluateResult::String(v.clone())),
+            Self::Nil => Ok(EvaluateResult::Nil),
+            Self::Binary { .. } => self.evaluate_binary(env),
+            Self::Unary { .. } => self.evaluate_unary(env),
+            // Evaluates the inner expression exactly once. Nothing else on the evaluation path runs
+            // a subtree again: tracing only displays nodes, and memoization skips impure ones.
+            Self::Group(node) => node.evaluate(env),
+            Self::Return(value) => {
+                let value = match value {
+                    Some(value) => value.evaluate(env)?,
+                    None => EvaluateResult::Nil,
+                };
+                env.set_return_value(value);
+                Err(Return.into())
+            }
+            Self::Print(expr) => {
+                let value = expr.evaluate(env)?;
+                writeln!(env.output(), "{}", value)?;
+                Ok(value)
+            }
+            // Evaluated for its side effects. The value only survives as the program's result
+            // when this is the last statement; everywhere else it is dropped.
+            Self::ExprStmt(expr) => expr.evaluate(env),
+            // `var x;` binds `x` to nil, so reading it is fine; reading a name that was never
+            // declared is an "Undefined variable" error.
+            Self::Variable { name, value } => {
+                let value = if let Some(v) = value { v.evaluate(env)? } else { EvaluateResult::Nil };
+                env.define(name, value.clone());
+                Ok(value)
+            }
+            Self::Const { name, value } => {
+                let value = value.evaluate(env)?;
+                env.define_constant(name, value.clone());
+                Ok(value)
+            }
+            Self::Global { name, value } => {
+                let value = value.evaluate(env)?;
+                env.bind_global(name, value.clone())?;
+                Ok(value)
+            }
+            // Each file is run at most once, which also breaks import cycles.
+            Self::Import(path) => {
+                let path = env.resolve_import(path);
+                let path = std::fs::canonicalize(&path).with_context(|| format!("Cannot import {}", path.display()))?;
+                if env.mark_imported(path.clone()) {
+                    let source = std::fs::read_to_string(&path)?;
+                    let options = ParseOptions {
+                        integer_only: env.integer_only(),
+                        ..ParseOptions::default()
+                    };
+                    let program = interpreting::parse(&source, &options)?;
+                    let previous = env.replace_current_file(Some(path));
+                    let result = evaluate_program(&program, env);
+                    env.replace_current_file(previous);
+                    env.forget_memoized();
+                    result?;
                 }
+                Ok(EvaluateResult::Nil)
+            }
+            Self::Destructure { names, value } => {
+                let value = value.evaluate(env)?;
+                let EvaluateResult::List(elements) = &value else {
+                    bail!("Can only destructure lists, got {}", value.type_name())
+                };
+                let elements = elements.borrow().clone();
+                if elements.len() != names.len() {
+                    bail!("Cannot destructure a list of {} elements into {} names", elements.len(), names.len())
+                }
+                for (name, element) in names.iter().zip(elements) {
+                    env.define(name, element);
+                }
+                Ok(value)
             }
             // The result of Block is now the result of the last expression in the block.
+            // Deferred statements run when the block exits, last registered first, still inside
+            // the block's scope. They run even when the body fails; the body's error is then the
+            // one reported, otherwise the first error from a deferred statement is.
             Self::Block(nodes) => {
-                let mut result = EvaluateResult::Nil;
+                env.forward();
+                let mut deferred = Vec::new();
+                let mut result = Ok(EvaluateResult::Nil);
                 for node in nodes {
-                    result = node.evaluate();
+                    result = match node {
+                        Self::Defer(statement) => {
+                            deferred.push(statement);
+                            Ok(EvaluateResult::Nil)
+                        }
+                        _ => node.evaluate(env),
+                    };
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                for statement in deferred.into_iter().rev() {
+                    let outcome = statement.evaluate(env);
+                    if let (Ok(_), Err(e)) = (&result, outcome) {
+                        result = Err(e);
+                    }
                 }
+                env.expire();
                 result
             }
+            Self::Defer(_) => bail!("defer is only allowed directly inside a block"),
+            Self::Identifier { name, depth } => match depth {
+                Some(depth) => env.get_at(*depth, name),
+                None => env.get_global(name),
+            },
+            Self::Assign { name, value, depth } => {
+                let value = value.evaluate(env)?;
+                match depth {
+                    Some(depth) => env.assign_at(*depth, name, value.clone())?,
+                    None => env.assign_global(name, value.clone())?,
+                }
+                Ok(value)
+            }
+            // Cases don't fall through: only the first matching case (or the default) runs.
+            Self::Switch { scrutinee, cases, default } => {
+                let scrutinee = scrutinee.evaluate(env)?;
+                for (value, body) in cases {
+                    if value.evaluate(env)? == scrutinee {
+                        return body.evaluate(env);
+                    }
+                }
+                match default {
+                    Some(default) => default.evaluate(env),
+                    None => Ok(EvaluateResult::Nil),
+                }
+            }
+            // The first arm whose pattern fits the value's type runs, in a scope of its own when it
+            // binds the value.
+            Self::Match { scrutinee, arms } => {
+                let value = scrutinee.evaluate(env)?;
+                let Some(arm) = arms.iter().find(|arm| arm.matches(value.type_name())) else {
+                    bail!("no match arm for {} {}", value.type_name(), value.repr())
+                };
+                let Some(binding) = &arm.binding else {
+                    return arm.body.evaluate(env);
+                };
+                env.forward();
+                env.define(binding, value);
+                let result = arm.body.evaluate(env);
+                env.expire();
+                result
+            }
+            Self::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = condition.evaluate(env)?;
+                if env.condition(&condition)? {
+                    then_branch.evaluate(env)
+                } else if let Some(else_branch) = else_branch {
+                    else_branch.evaluate(env)
+                } else {
+                    Ok(EvaluateResult::Nil)
+                }
+            }
+            // The callee is evaluated first, then the arguments strictly left to right.
+            Self::Call { callee, arguments } => {
+                let callee = callee.evaluate(env)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(env))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                match callee {
+                    EvaluateResult::NativeFunction(function) => function.call(env, &arguments),
+                    _ => bail!("Can only call functions, got {}", callee),
+                }
+            }
+            // Lists and strings take an integer position counted from 0, strings by character.
+            // A map gives nil for a missing key, like `get`.
+            Self::Index { object, index } => {
+                let object = object.evaluate(env)?;
+                let index = index.evaluate(env)?;
+                let position = |len: usize| match index {
+                    EvaluateResult::Number(Number::Integer(i)) => match usize::try_from(i) {
+                        Ok(position) if position < len => Ok(position),
+                        _ => bail!("index {} out of range for {} of length {}", i, object.type_name(), len),
+                    },
+                    _ => bail!("{} index must be an integer, got {}", object.type_name(), index.type_name()),
+                };
+                match &object {
+                    EvaluateResult::List(elements) => {
+                        let elements = elements.borrow();
+                        Ok(elements[position(elements.len())?].clone())
+                    }
+                    EvaluateResult::String(s) => {
+                        let position = position(s.chars().count())?;
+                        Ok(EvaluateResult::String(s.chars().nth(position).unwrap().to_string()))
+                    }
+                    EvaluateResult::Map(entries) => Ok(map_get(&entries.borrow(), &MapKey::new(&index)?)),
+                    _ => bail!("Can only index lists, strings and maps, got {}", object.type_name()),
+                }
+            }
+            Self::List(elements) => {
+                let elements = elements.iter().map(|element| element.evaluate(env)).collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(EvaluateResult::list(elements))
+            }
+            Self::ForIn { .. } | Self::While { .. } | Self::Repeat { .. } => self.evaluate_loop(env, None),
+            Self::Labeled { label, body } => body.evaluate_loop(env, Some(label)),
+            Self::Break(label) => Err(LoopSignal::Break(label.clone()).into()),
+            Self::Continue(label) => Err(LoopSignal::Continue(label.clone()).into()),
+            Self::Sequence(nodes) => {
+                let mut result = EvaluateResult::Nil;
+                for node in nodes {
+                    result = node.evaluate(env)?;
+                }
+                Ok(result)
+            }
+            // `right` is only evaluated when `left` is nil.
+            Self::NilCoalesce { left, right } => match left.evaluate(env)? {
+                EvaluateResult::Nil => right.evaluate(env),
+                value => Ok(value),
+            },
+        }
+    }
+
+    /// Runs a `for in`, `while` or `repeat` loop, which `break` and `continue` without a label or
+    /// with `label` apply to. Signals aimed at an outer loop pass through.
+    fn evaluate_loop(
+        &self,
+        env: &mut Environment,
+        label: Option<&str>,
+    ) -> anyhow::Result<EvaluateResult> {
+        match self {
+            // Every iteration binds `name` in a fresh scope. The loop walks a snapshot of the list,
+            // so pushing to it from the body doesn't extend the loop.
+            Self::ForIn { name, iterable, body } => {
+                let elements = match iterable.evaluate(env)? {
+                    EvaluateResult::List(elements) => elements.borrow().clone(),
+                    other => bail!("Can only iterate over lists, got {}", other),
+                };
+                for element in elements {
+                    env.forward();
+                    env.define(name, element);
+                    let result = body.evaluate(env);
+                    env.expire();
+                    if !keep_looping(result, label)? {
+                        break;
+                    }
+                }
+                Ok(EvaluateResult::Nil)
+            }
+            // A body that only assigns arithmetic to existing variables leaves its block scope
+            // empty, so it runs as a copy resolved against the enclosing scope instead of pushing
+            // and popping a scope every iteration. Tracing shows the real nodes, so it opts out, as
+            // does memoization: the copy is freed after the loop, and the cache goes by address.
+            Self::While { condition, body } => {
+                let unscoped = if env.trace() || env.memoizing() { None } else { unscoped_body(body) };
+                loop {
+                    let value = condition.evaluate(env)?;
+                    if !env.condition(&value)? {
+                        break;
+                    }
+                    match &unscoped {
+                        Some(statements) => {
+                            // The step the block itself would have counted.
+                            env.count_step()?;
+                            for statement in statements {
+                                statement.evaluate(env)?;
+                            }
+                        }
+                        None => {
+                            if !keep_looping(body.evaluate(env), label)? {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Ok(EvaluateResult::Nil)
+            }
+            // Like `for in`, every iteration runs in a fresh scope.
+            Self::Repeat { count, body } => {
+                let times = match count.evaluate(env)? {
+                    EvaluateResult::Number(Number::Integer(n)) if n >= 0 => n,
+                    other => bail!("repeat count must be a non-negative integer, got {}", other),
+                };
+                for _ in 0..times {
+                    env.forward();
+                    let result = body.evaluate(env);
+                    env.expire();
+                    if !keep_looping(result, label)? {
+                        break;
+                    }
+                }
+                Ok(EvaluateResult::Nil)
+            }
+            _ => panic!("Invalid loop node"),
         }
     }
 
-    fn evaluate_binary(&self) -> EvaluateResult {
+    fn evaluate_binary(
+        &self,
+        env: &mut Environment,
+    ) -> anyhow::Result<EvaluateResult> {
         match self {
+            // `and` and `or` yield one of their operands and skip the right one when the left
+            // decides the result: `nil and x` is nil without evaluating `x`.
+            Self::Binary { operator, left, right } if operator == "and" || operator == "or" => {
+                let left = left.evaluate(env)?;
+                if env.condition(&left)? == (operator == "or") {
+                    return Ok(left);
+                }
+                right.evaluate(env)
+            }
+            // Operands are evaluated left then right, before the operator is looked at.
+            // Values of different types are never equal, so `nil == false` is false.
             Self::Binary { operator, left, right } => {
-                let left = left.evaluate();
-                let right = right.evaluate();
-                match (left, right) {
+                let left = left.evaluate(env)?;
+                let right = right.evaluate(env)?;
+                if let Some(overridden) = env.operator(operator) {
+                    if let Some(result) = overridden(&left, &right)? {
+                        return Ok(result);
+                    }
+                }
+                let result = match (left, right) {
+                    (left, right) if operator == "==" => EvaluateResult::Boolean(left == right),
+                    (left, right) if operator == "!=" => EvaluateResult::Boolean(left != right),
+                    (left, right) if matches!(operator.as_str(), ">" | "<" | ">=" | "<=") => {
+                        let ordering = left.partial_compare(&right)?;
+                        EvaluateResult::Boolean(match (operator.as_str(), ordering) {
+                            (_, None) => false,
+                            (">", Some(ordering)) => ordering.is_gt(),
+                            ("<", Some(ordering)) => ordering.is_lt(),
+                            (">=", Some(ordering)) => ordering.is_ge(),
+                            (_, Some(ordering)) => ordering.is_le(),
+                        })
+                    }
+                    (EvaluateResult::Number(left), EvaluateResult::Number(right)) if env.saturating() => match operator.as_str() {
+                        "+" => EvaluateResult::Number(left.saturating_add(right)),
+                        "-" => EvaluateResult::Number(left.saturating_sub(right)),
+                        "*" => EvaluateResult::Number(left.saturating_mul(right)),
+                        "/" => EvaluateResult::Number(left.checked_div(right)?),
+                        "%" => EvaluateResult::Number(left.checked_rem(right)?),
+                        "**" => EvaluateResult::Number(left.saturating_pow(right)?),
+                        ".." => range(left, right)?,
+                        _ => bail!("cannot apply '{}' to number and number", operator),
+                    },
                     (EvaluateResult::Number(left), EvaluateResult::Number(right)) => match operator.as_str() {
-                        "+" => EvaluateResult::Number(left + right),
-                        "-" => EvaluateResult::Number(left - right),
-                        "*" => EvaluateResult::Number(left * right),
-                        "/" => EvaluateResult::Number(left / right),
-                        ">" => EvaluateResult::Boolean(left > right),
-                        "<" => EvaluateResult::Boolean(left < right),
-                        "==" => EvaluateResult::Boolean(left == right),
-                        "!=" => EvaluateResult::Boolean(left != right),
-                        ">=" => EvaluateResult::Boolean(left >= right),
-                        "<=" => EvaluateResult::Boolean(left <= right),
-                        _ => panic!("Invalid operator"),
+                        "+" => EvaluateResult::Number(left.checked_add(right)?),
+                        "-" => EvaluateResult::Number(left.checked_sub(right)?),
+                        "*" => EvaluateResult::Number(left.checked_mul(right)?),
+                        "/" => EvaluateResult::Number(left.checked_div(right)?),
+                        "%" => EvaluateResult::Number(left.checked_rem(right)?),
+                        "**" => EvaluateResult::Number(left.checked_pow(right)?),
+                        ".." => range(left, right)?,
+                        _ => bail!("cannot apply '{}' to number and number", operator),
                     },
                     (EvaluateResult::String(left), EvaluateResult::String(right)) => match operator.as_str() {
                         "+" => EvaluateResult::String(left.add(&right)),
-                        "==" => EvaluateResult::Boolean(left == right),
-                        _ => panic!("Invalid operator"),
+                        _ => bail!("cannot apply '{}' to string and string", operator),
                     },
-                    _ => panic!("Invalid operands"),
+                    // `"ab" * 3` is "ababab"; a zero count gives the empty string.
+                    (EvaluateResult::String(left), EvaluateResult::Number(count)) if operator == "*" => {
+                        let Number::Integer(count) = count else {
+                            bail!("repeat count must be an integer")
+                        };
+                        let Ok(count) = usize::try_from(count) else {
+                            bail!("negative repeat count")
+                        };
+                        match left.len().checked_mul(count) {
+                            Some(len) if len <= MAX_REPEAT_LEN => EvaluateResult::String(left.repeat(count)),
+                            _ => bail!("repeated string would be longer than {} bytes", MAX_REPEAT_LEN),
+                        }
+                    }
+                    (left, right) => bail!("cannot apply '{}' to {} and {}", operator, left.type_name(), right.type_name()),
+                };
+                if env.integer_only() && matches!(result, EvaluateResult::Number(Number::Float(_))) {
+                    bail!("floats are disabled")
                 }
+                Ok(result)
             }
             _ => panic!("Invalid binary node"),
         }
     }
 
-    fn evaluate_unary(&self) -> EvaluateResult {
+    fn evaluate_unary(
+        &self,
+        env: &mut Environment,
+    ) -> anyhow::Result<EvaluateResult> {
         match self {
             Self::Unary { operator, operand } => {
-                let op = operand.evaluate();
-                match op {
-                    EvaluateResult::Number(number) => match operator {
-                        '-' => EvaluateResult::Number(-number),
-                        _ => panic!("Invalid operator"),
-                    },
-                    EvaluateResult::Boolean(v) => match operator {
-                        '!' => EvaluateResult::Boolean(!v),
-                        _ => panic!("Invalid operator"),
-                    },
-                    _ => panic!("Invalid operand"),
+                let op = operand.evaluate(env)?;
+                if *operator == '+' {
+                    return match op {
+                        EvaluateResult::Number(_) => Ok(op),
+                        _ => bail!("Operand of unary '+' must be a number, got {}", op),
+                    };
                 }
+                // `!` negates truthiness, so `!nil` is true.
+                let result = match (operator, op) {
+                    ('!', op) => EvaluateResult::Boolean(!op.is_truthy()),
+                    ('-', EvaluateResult::Number(number)) if env.saturating() => EvaluateResult::Number(number.saturating_neg()),
+                    ('-', EvaluateResult::Number(number)) => EvaluateResult::Number(number.checked_neg()?),
+                    (_, op) => bail!("Operand of unary '{}' must be a number, got {}", operator, op),
+                };
+                Ok(result)
             }
             _ => panic!("Invalid unary node"),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum EvaluateResult {
     Boolean(bool),
     Number(Number),
     String(String),
     Nil,
+    NativeFunction(NativeFunction),
+    /// Copies of a list value share its elements, so `push` through one is seen through all.
+    List(Rc<RefCell<Vec<EvaluateResult>>>),
+    /// Entries are kept in insertion order, which is the order they print and iterate in;
+    /// updating an existing key keeps its place. Copies share entries like lists do.
+    Map(Rc<RefCell<Vec<(MapKey, EvaluateResult)>>>),
+}
+
+/// `start..end` is the list of integers from `start` up to, but excluding, `end`.
+fn range(
+    start: Number,
+    end: Number,
+) -> anyhow::Result<EvaluateResult> {
+    match (start, end) {
+        (Number::Integer(start), Number::Integer(end)) => {
+            if i128::from(end) - i128::from(start) > MAX_RANGE_LEN as i128 {
+                bail!("range {}..{} would have more than {} elements", start, end, MAX_RANGE_LEN)
+            }
+            Ok(EvaluateResult::list(
+                (start..end).map(|i| EvaluateResult::Number(Number::Integer(i))).collect(),
+            ))
+        }
+        _ => bail!("Range bounds must be integers, got {}..{}", start, end),
+    }
+}
+
+/// The statements of `body` re-resolved to skip its block scope, when that scope is sure to stay
+/// empty: `body` is a block of assignments to already declared variables, whose values are
+/// arithmetic over numbers and variables. Anything else, such as a call or a declaration, gives
+/// `None`.
+fn unscoped_body(body: &AstNode) -> Option<Vec<AstNode>> {
+    let AstNode::Block(statements) = body else { return None };
+    statements
+        .iter()
+        .map(|statement| match statement {
+            AstNode::ExprStmt(expr) => Some(AstNode::ExprStmt(Box::new(unscoped(expr)?))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn unscoped(node: &AstNode) -> Option<AstNode> {
+    // Nothing is declared in the block, so a resolved local is at least one scope out.
+    let outer = |depth: &Option<usize>| match depth {
+        Some(depth) => depth.checked_sub(1).map(Some),
+        None => Some(None),
+    };
+    Some(match node {
+        AstNode::Number(_) => node.clone(),
+        AstNode::Identifier { name, depth } => AstNode::Identifier {
+            name: name.clone(),
+            depth: outer(depth)?,
+        },
+        AstNode::Assign { name, value, depth } if !matches!(**value, AstNode::Assign { .. }) => AstNode::Assign {
+            name: name.clone(),
+            value: Box::new(unscoped(value)?),
+            depth: outer(depth)?,
+        },
+        AstNode::Group(inner) => AstNode::Group(Box::new(unscoped(inner)?)),
+        AstNode::Unary { operator, operand } => AstNode::Unary {
+            operator: *operator,
+            operand: Box::new(unscoped(operand)?),
+        },
+        AstNode::Binary { left, operator, right } if operator != ".." => AstNode::Binary {
+            left: Box::new(unscoped(left)?),
+            operator: operator.clone(),
+            right: Box::new(unscoped(right)?),
+        },
+        _ => return None,
+    })
+}
+
+/// Natives get the calling environment as context, e.g. to inspect globals.
+pub type NativeFn = dyn Fn(&mut Environment, &[EvaluateResult]) -> anyhow::Result<EvaluateResult>;
+
+/// A host override for a binary operator. Returning `Ok(None)` declines the operands and falls
+/// back to the built-in behavior.
+pub type BinaryOperatorFn = dyn Fn(&EvaluateResult, &EvaluateResult) -> anyhow::Result<Option<EvaluateResult>>;
+
+/// A function implemented in Rust and callable from Lox.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    /// `None` for natives taking any number of arguments.
+    pub arity: Option<usize>,
+    pub function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Environment, &[EvaluateResult]) -> anyhow::Result<EvaluateResult> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity: Some(arity),
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn variadic(
+        name: &str,
+        function: impl Fn(&mut Environment, &[EvaluateResult]) -> anyhow::Result<EvaluateResult> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity: None,
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn call(
+        &self,
+        env: &mut Environment,
+        arguments: &[EvaluateResult],
+    ) -> anyhow::Result<EvaluateResult> {
+        if let Some(arity) = self.arity {
+            if arguments.len() != arity {
+                bail!("{} expected {} arguments but got {}", self.name, arity, arguments.len())
+            }
+        }
+        (self.function)(env, arguments)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(
+        &self,
+        other: &Self,
+    ) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl EvaluateResult {
+    pub fn list(elements: Vec<EvaluateResult>) -> Self {
+        EvaluateResult::List(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn map(entries: Vec<(MapKey, EvaluateResult)>) -> Self {
+        EvaluateResult::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// `nil` and `false` are falsy, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, EvaluateResult::Nil | EvaluateResult::Boolean(false))
+    }
+
+    /// Like `Display`, but strings are quoted so `"1"` and `1` look different, e.g. at the REPL.
+    pub fn repr(&self) -> String {
+        match self {
+            EvaluateResult::String(s) => format!("{:?}", s),
+            EvaluateResult::List(elements) => format!(
+                "[{}]",
+                elements.borrow().iter().map(EvaluateResult::repr).collect::<Vec<String>>().join(", ")
+            ),
+            EvaluateResult::Map(entries) => format!(
+                "{{{}}}",
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key.to_value().repr(), value.repr()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Orders two values: numbers by value, promoting an integer to a float when the other side is
+    /// one, strings lexicographically, and booleans with `false` before `true`. Any other pairing,
+    /// or a NaN, is an error.
+    pub fn compare(
+        &self,
+        other: &Self,
+    ) -> anyhow::Result<Ordering> {
+        match self.partial_compare(other)? {
+            Some(ordering) => Ok(ordering),
+            None => bail!("cannot compare {} and {}", self, other),
+        }
+    }
+
+    /// Like `compare`, but a NaN operand is unordered (`None`) rather than an error, which is what
+    /// the `<`/`>` operators need: every comparison with NaN is false.
+    pub fn partial_compare(
+        &self,
+        other: &Self,
+    ) -> anyhow::Result<Option<Ordering>> {
+        match (self, other) {
+            (EvaluateResult::Number(a), EvaluateResult::Number(b)) => Ok(a.partial_cmp(b)),
+            (EvaluateResult::String(a), EvaluateResult::String(b)) => Ok(Some(a.cmp(b))),
+            (EvaluateResult::Boolean(a), EvaluateResult::Boolean(b)) => Ok(Some(a.cmp(b))),
+            _ => bail!("cannot compare {} and {}", self.type_name(), other.type_name()),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            EvaluateResult::Boolean(_) => "boolean",
+            EvaluateResult::Number(_) => "number",
+            EvaluateResult::String(_) => "string",
+            EvaluateResult::Nil => "nil",
+            EvaluateResult::NativeFunction(_) => "function",
+            EvaluateResult::List(_) => "list",
+            EvaluateResult::Map(_) => "map",
+        }
+    }
+
+    pub fn to_bool_strict(&self) -> anyhow::Result<bool> {
+        match self {
+            EvaluateResult::Boolean(v) => Ok(*v),
+            _ => bail!("condition must be a boolean"),
+        }
+    }
+}
+
+impl Display for EvaluateResult {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            EvaluateResult::Boolean(v) => write!(f, "{}", v),
+            EvaluateResult::Number(number) => write!(f, "{}", number),
+            EvaluateResult::String(s) => write!(f, "{}", s),
+            EvaluateResult::Nil => write!(f, "nil"),
+            EvaluateResult::NativeFunction(function) => write!(f, "{:?}", function),
+            EvaluateResult::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            EvaluateResult::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key.to_value(), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A value usable as a map key. `f64` is neither `Hash` nor `Eq`, so floats are stored by bit
+/// pattern: `-0.0` is folded into `0.0` and every NaN into one canonical NaN, so a NaN key can be
+/// found again even though `NaN == NaN` is false. As with `==`, an integer and a float are never
+/// the same key, so `map[1]` and `map[1.0]` are separate entries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Boolean(bool),
+    Integer(i64),
+    Float(u64),
+    String(String),
+    Nil,
+}
+
+/// The value stored under `key` in a map's entries, or nil when there is none.
+pub(crate) fn map_get(
+    entries: &[(MapKey, EvaluateResult)],
+    key: &MapKey,
+) -> EvaluateResult {
+    entries
+        .iter()
+        .find(|(existing, _)| existing == key)
+        .map_or(EvaluateResult::Nil, |(_, value)| value.clone())
+}
+
+impl MapKey {
+    pub fn new(value: &EvaluateResult) -> anyhow::Result<Self> {
+        let key = match value {
+            EvaluateResult::Boolean(v) => MapKey::Boolean(*v),
+            EvaluateResult::Number(Number::Integer(i)) => MapKey::Integer(*i),
+            EvaluateResult::Number(Number::Float(f)) if f.is_nan() => MapKey::Float(f64::NAN.to_bits()),
+            EvaluateResult::Number(Number::Float(f)) if *f == 0.0 => MapKey::Float(0.0f64.to_bits()),
+            EvaluateResult::Number(Number::Float(f)) => MapKey::Float(f.to_bits()),
+            EvaluateResult::String(s) => MapKey::String(s.clone()),
+            EvaluateResult::Nil => MapKey::Nil,
+            _ => bail!("{} can't be used as a map key", value.type_name()),
+        };
+        Ok(key)
+    }
+
+    pub fn to_value(&self) -> EvaluateResult {
+        match self {
+            MapKey::Boolean(v) => EvaluateResult::Boolean(*v),
+            MapKey::Integer(i) => EvaluateResult::Number(Number::Integer(*i)),
+            MapKey::Float(bits) => EvaluateResult::Number(Number::Float(f64::from_bits(*bits))),
+            MapKey::String(s) => EvaluateResult::String(s.clone()),
+            MapKey::Nil => EvaluateResult::Nil,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{lexing::lexing, parsing::Parser, token::TokenType};
+    use std::{cmp::Ordering, collections::HashMap};
+
+    use super::{EvaluateResult, MapKey};
+    use crate::{
+        ast::AstNode,
+        environment::Environment,
+        lexing::lexing,
+        parsing::Parser,
+        resolving::Resolver,
+        token::{Number, TokenType},
+    };
 
     #[test]
     fn evaluate() {
@@ -101,10 +924,366 @@ mod tests {
         let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
         println!("{:?}", tokens);
         let ast = Parser::new(tokens).parse().unwrap();
+        let mut env = Environment::default();
         for node in ast {
             println!("{}", node);
-            let result = node.evaluate();
+            let result = node.evaluate(&mut env).unwrap();
             println!("{:?}", result);
         }
     }
+
+    #[test]
+    fn switch() {
+        let tokens = lexing("tests/switch.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let mut ast = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut ast);
+        let mut env = Environment::default();
+        let results = ast.iter().map(|node| node.evaluate(&mut env).unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                EvaluateResult::Number(Number::Integer(2)),
+                EvaluateResult::String("two".to_string()),
+                EvaluateResult::String("many".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        let tokens = lexing("tests/if.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let mut ast = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut ast);
+        let mut env = Environment::default();
+        let results = ast.iter().map(|node| node.evaluate(&mut env).unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                EvaluateResult::Number(Number::Integer(3)),
+                EvaluateResult::String("big".to_string()),
+                EvaluateResult::String("small".to_string()),
+                EvaluateResult::Nil,
+            ]
+        );
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let tokens = lexing("tests/division.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut env = Environment::default();
+        let results = ast
+            .iter()
+            .map(|node| node.evaluate(&mut env).map_err(|e| e.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(EvaluateResult::Number(Number::Integer(1))),
+                Err("Division by zero".to_string()),
+                Err("Remainder by zero".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_conditions() {
+        let tokens = lexing("tests/condition.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let ast = Parser::new(tokens).parse().unwrap();
+
+        let mut env = Environment::default();
+        assert_eq!(ast[0].evaluate(&mut env).unwrap(), EvaluateResult::String("yes".to_string()));
+
+        let mut env = Environment::default().with_strict_conditions();
+        let err = ast[0].evaluate(&mut env).unwrap_err();
+        assert_eq!(err.to_string(), "condition must be a boolean");
+    }
+
+    #[test]
+    fn step_through() {
+        let tokens = lexing("tests/step.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let mut ast = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut ast);
+        let mut env = Environment::default();
+
+        super::step(&ast[0], &mut env).unwrap();
+        assert_eq!(env.get("a").unwrap(), EvaluateResult::Number(Number::Integer(1)));
+        assert!(env.get("b").is_err());
+
+        super::step(&ast[1], &mut env).unwrap();
+        assert_eq!(env.get("a").unwrap(), EvaluateResult::Number(Number::Integer(1)));
+        assert_eq!(env.get("b").unwrap(), EvaluateResult::Number(Number::Integer(3)));
+    }
+
+    #[test]
+    fn unary_plus() {
+        assert_eq!(crate::run("+5;").unwrap(), EvaluateResult::Number(Number::Integer(5)));
+        assert_eq!(crate::run("+(-3);").unwrap(), EvaluateResult::Number(Number::Integer(-3)));
+        let err = crate::run("+\"x\";").unwrap_err();
+        assert_eq!(err.to_string(), "Operand of unary '+' must be a number, got x");
+    }
+
+    #[test]
+    fn for_in() {
+        let tokens = lexing("tests/for_in.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let mut ast = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut ast);
+        let mut env = Environment::default();
+        super::evaluate_program(&ast, &mut env).unwrap();
+        assert_eq!(env.get("total").unwrap(), EvaluateResult::Number(Number::Integer(6)));
+        assert_eq!(env.get("sum").unwrap(), EvaluateResult::Number(Number::Integer(10)));
+        assert!(env.get("x").is_err());
+
+        let err = crate::run("var r = 0..1000000000000;").unwrap_err();
+        assert_eq!(err.to_string(), "range 0..1000000000000 would have more than 16777216 elements");
+        let err = crate::run(&format!("var r = -{}..{};", i64::MAX, i64::MAX)).unwrap_err();
+        assert!(err.to_string().ends_with("would have more than 16777216 elements"));
+        assert_eq!(crate::run("var r = 5..0; r;").unwrap(), EvaluateResult::list(Vec::new()));
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let err = crate::run("[1, 2] + 3;").unwrap_err();
+        assert_eq!(err.to_string(), "cannot apply '+' to list and number");
+    }
+
+    #[test]
+    fn power() {
+        assert_eq!(crate::run("2 ** 3 ** 2;").unwrap(), EvaluateResult::Number(Number::Integer(512)));
+        assert_eq!(crate::run("-2 ** 2;").unwrap(), EvaluateResult::Number(Number::Integer(-4)));
+        assert_eq!(crate::run("2 ** -2;").unwrap(), EvaluateResult::Number(Number::Float(0.25)));
+        assert_eq!(crate::run("2 ** 100;").unwrap_err().to_string(), "Integer overflow in 2 ** 100");
+    }
+
+    #[test]
+    fn map_keys() {
+        let key = |n: Number| MapKey::new(&EvaluateResult::Number(n)).unwrap();
+        let mut map = HashMap::new();
+        map.insert(key(Number::Integer(1)), "int");
+        map.insert(key(Number::Float(1.0)), "float");
+        map.insert(key(Number::Float(0.0)), "zero");
+        map.insert(key(Number::Float(f64::NAN)), "nan");
+
+        assert_eq!(map[&key(Number::Integer(1))], "int");
+        assert_eq!(map[&key(Number::Float(1.0))], "float");
+        assert_eq!(map[&key(Number::Float(-0.0))], "zero");
+        assert_eq!(map[&key(Number::Float(-f64::NAN))], "nan");
+        assert_eq!(key(Number::Float(2.5)).to_value(), EvaluateResult::Number(Number::Float(2.5)));
+
+        let err = MapKey::new(&EvaluateResult::list(vec![])).unwrap_err();
+        assert_eq!(err.to_string(), "list can't be used as a map key");
+    }
+
+    #[test]
+    fn compare() {
+        let int = |i| EvaluateResult::Number(Number::Integer(i));
+        let float = |f| EvaluateResult::Number(Number::Float(f));
+        let string = |s: &str| EvaluateResult::String(s.to_string());
+        assert_eq!(int(1).compare(&int(2)).unwrap(), Ordering::Less);
+        assert_eq!(int(2).compare(&float(1.5)).unwrap(), Ordering::Greater);
+        assert_eq!(float(2.0).compare(&int(2)).unwrap(), Ordering::Equal);
+        assert_eq!(string("apple").compare(&string("banana")).unwrap(), Ordering::Less);
+        assert_eq!(crate::run("\"b\" >= \"a\";").unwrap(), EvaluateResult::Boolean(true));
+
+        let err = int(1).compare(&string("1")).unwrap_err();
+        assert_eq!(err.to_string(), "cannot compare number and string");
+
+        // Every ordering operator rejects mismatched operands, while equality just says no.
+        for operator in ["<", "<=", ">", ">="] {
+            let err = crate::run(&format!("1 {} \"2\";", operator)).unwrap_err();
+            assert_eq!(err.to_string(), "cannot compare number and string");
+            let err = crate::run(&format!("\"2\" {} 1.5;", operator)).unwrap_err();
+            assert_eq!(err.to_string(), "cannot compare string and number");
+        }
+        assert_eq!(crate::run("1 == \"1\";").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(crate::run("1 != \"1\";").unwrap(), EvaluateResult::Boolean(true));
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(crate::run("true == true;").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(crate::run("true != false;").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(crate::run("false == true;").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(crate::run("false < true;").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(crate::run("true <= false;").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(crate::run("sort([true, false]);").unwrap(), crate::run("[false, true];").unwrap());
+
+        let err = crate::run("true < 1;").unwrap_err();
+        assert_eq!(err.to_string(), "cannot compare boolean and number");
+    }
+
+    #[test]
+    fn repeat() {
+        let result = crate::run("var n = 0;\nrepeat 3 { var step = 2; n = n + step; }\nn;").unwrap();
+        assert_eq!(result, EvaluateResult::Number(Number::Integer(6)));
+        assert_eq!(
+            crate::run("var n = 0;\nrepeat 0 n = n + 1;\nn;").unwrap(),
+            EvaluateResult::Number(Number::Integer(0))
+        );
+
+        let err = crate::run("repeat -1 {}").unwrap_err();
+        assert_eq!(err.to_string(), "repeat count must be a non-negative integer, got -1");
+        let err = crate::run("repeat 2.5 {}").unwrap_err();
+        assert_eq!(err.to_string(), "repeat count must be a non-negative integer, got 2.5");
+    }
+
+    #[test]
+    fn separate_environments() {
+        let program = crate::interpreting::parse("n = n + (2 * 1 - 1);", &Default::default()).unwrap();
+        let mut first = Environment::default().with_memoization();
+        first.define_global("n", EvaluateResult::Number(Number::Integer(10)));
+        let mut second = Environment::default();
+        second.define_global("n", EvaluateResult::Number(Number::Integer(0)));
+
+        assert_eq!(
+            super::evaluate_in(&program, &mut first).unwrap(),
+            EvaluateResult::Number(Number::Integer(11))
+        );
+        assert_eq!(
+            super::evaluate_in(&program, &mut second).unwrap(),
+            EvaluateResult::Number(Number::Integer(1))
+        );
+        assert_eq!(
+            super::evaluate_in(&program, &mut first).unwrap(),
+            EvaluateResult::Number(Number::Integer(12))
+        );
+    }
+
+    #[test]
+    fn while_fast_path() {
+        // The leading `var` keeps the second loop on the general, scope-per-iteration path.
+        let body = "acc = acc * 2 + (i % 3); i = i + 1; total = total - -acc;";
+        let program = |prefix: &str| {
+            format!(
+                "var total = 0;\n{{\n    var i = 0;\n    var acc = 1;\n    while i < 20 {{ {}{} }}\n}}\ntotal;",
+                prefix, body
+            )
+        };
+        let fast = crate::run(&program("")).unwrap();
+        assert_eq!(fast, crate::run(&program("var unused;")).unwrap());
+        assert_eq!(fast, EvaluateResult::Number(Number::Integer(3295501)));
+
+        let AstNode::While { body, .. } = &crate::interpreting::parse("while x { x = x + 1; }", &Default::default()).unwrap()[0] else {
+            panic!("expected while")
+        };
+        assert!(super::unscoped_body(body).is_some());
+        for body in ["{ x = f(x); }", "{ var y = 1; }", "{ print x; }", "{ x = [x]; }"] {
+            let source = format!("while x {}", body);
+            let ast = crate::interpreting::parse(&source, &Default::default()).unwrap();
+            let AstNode::While { body, .. } = &ast[0] else {
+                panic!("expected while")
+            };
+            assert!(super::unscoped_body(body).is_none(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn match_types() {
+        let describe = "var describe = match value { number n => n * 2, string s => s + \"!\", nil => \"nothing\", _ => \"other\" };\ndescribe;";
+        let run = |value: &str| crate::run(&format!("var value = {};\n{}", value, describe)).unwrap();
+        assert_eq!(run("21"), EvaluateResult::Number(Number::Integer(42)));
+        assert_eq!(run("\"hi\""), EvaluateResult::String("hi!".to_string()));
+        assert_eq!(run("nil"), EvaluateResult::String("nothing".to_string()));
+        assert_eq!(run("[1]"), EvaluateResult::String("other".to_string()));
+
+        // The binding shadows an outer variable only inside its arm.
+        let result = crate::run("var n = 1;\nvar doubled = match 5 { number n => n + n };\nn + doubled;").unwrap();
+        assert_eq!(result, EvaluateResult::Number(Number::Integer(11)));
+
+        let err = crate::run("match true { number n => n };").unwrap_err();
+        assert_eq!(err.to_string(), "no match arm for boolean true");
+        let err = crate::run("match 1 { integer i => i };").unwrap_err();
+        assert_eq!(err.to_string(), "Expected a type name or '_' in match arm, got 'integer'");
+    }
+
+    #[test]
+    fn string_operators() {
+        assert_eq!(
+            crate::run("\"a\" - \"b\";").unwrap_err().to_string(),
+            "cannot apply '-' to string and string"
+        );
+        assert_eq!(
+            crate::run("\"a\" .. \"b\";").unwrap_err().to_string(),
+            "cannot apply '..' to string and string"
+        );
+        assert_eq!(
+            crate::run("\"a\" * \"b\";").unwrap_err().to_string(),
+            "cannot apply '*' to string and string"
+        );
+    }
+
+    #[test]
+    fn string_repeat() {
+        assert_eq!(crate::run("\"ab\" * 3;").unwrap(), EvaluateResult::String("ababab".to_string()));
+        assert_eq!(crate::run("\"x\" * 0;").unwrap(), EvaluateResult::String(String::new()));
+        assert_eq!(crate::run("\"x\" * -1;").unwrap_err().to_string(), "negative repeat count");
+        assert_eq!(crate::run("\"x\" * 2.0;").unwrap_err().to_string(), "repeat count must be an integer");
+        let err = crate::run("\"ab\" * 9223372036854775807;").unwrap_err();
+        assert_eq!(err.to_string(), "repeated string would be longer than 1073741824 bytes");
+        assert_eq!(crate::run("\"\" * 9223372036854775807;").unwrap(), EvaluateResult::String(String::new()));
+        assert_eq!(crate::run("3 * \"x\";").unwrap_err().to_string(), "cannot apply '*' to number and string");
+    }
+
+    #[test]
+    fn index() {
+        let run = |source: &str| crate::run(&format!("var l = [10, 20, 30];\nvar s = \"héy\";\n{}", source));
+        assert_eq!(run("l[0] + l[2];").unwrap(), EvaluateResult::Number(Number::Integer(40)));
+        assert_eq!(run("s[1];").unwrap(), EvaluateResult::String("é".to_string()));
+        assert_eq!(run("[[1, 2], [3]][0][1];").unwrap(), EvaluateResult::Number(Number::Integer(2)));
+        assert_eq!(run("l[3];").unwrap_err().to_string(), "index 3 out of range for list of length 3");
+        assert_eq!(run("l[-1];").unwrap_err().to_string(), "index -1 out of range for list of length 3");
+        assert_eq!(run("s[5];").unwrap_err().to_string(), "index 5 out of range for string of length 3");
+        assert_eq!(run("l[1.0];").unwrap_err().to_string(), "list index must be an integer, got number");
+        assert_eq!(
+            run("var m = map();\nset(m, \"k\", 1);\nm[\"k\"];").unwrap(),
+            EvaluateResult::Number(Number::Integer(1))
+        );
+        assert_eq!(run("nil[0];").unwrap_err().to_string(), "Can only index lists, strings and maps, got nil");
+    }
+
+    #[test]
+    fn nil_coalesce() {
+        assert_eq!(crate::run("nil ?? 5;").unwrap(), EvaluateResult::Number(Number::Integer(5)));
+        assert_eq!(crate::run("3 ?? undefined;").unwrap(), EvaluateResult::Number(Number::Integer(3)));
+        assert_eq!(
+            crate::run("var x;\nx ?? nil ?? \"default\";").unwrap(),
+            EvaluateResult::String("default".to_string())
+        );
+        assert_eq!(crate::run("false ?? 1;").unwrap(), EvaluateResult::Boolean(false));
+    }
+}
+
+#[cfg(test)]
+mod nil_semantics {
+    use super::EvaluateResult;
+    use crate::run;
+
+    #[test]
+    fn equality() {
+        assert_eq!(run("nil == nil;").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(run("nil == false;").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(run("nil != 0;").unwrap(), EvaluateResult::Boolean(true));
+    }
+
+    #[test]
+    fn logic() {
+        assert_eq!(run("!nil;").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(run("nil and 1;").unwrap(), EvaluateResult::Nil);
+        assert_eq!(run("nil and undefined;").unwrap(), EvaluateResult::Nil);
+        assert_eq!(run("nil or 1;").unwrap(), run("1;").unwrap());
+        assert_eq!(run("var x;\nif nil x = 1; else x = 2;\nx;").unwrap(), run("2;").unwrap());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("nil + 1;").unwrap_err().to_string(), "cannot apply '+' to nil and number");
+        assert_eq!(run("-nil;").unwrap_err().to_string(), "Operand of unary '-' must be a number, got nil");
+        assert_eq!(run("nil < 1;").unwrap_err().to_string(), "cannot compare nil and number");
+    }
 }
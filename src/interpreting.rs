@@ -0,0 +1,923 @@
+use std::{io::Write, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    ast::AstNode,
+    environment::Environment,
+    evaluating::{caught_return, evaluate_program, evaluate_until_return, step, EvaluateResult, NativeFunction},
+    lexing::{lexing_str, lexing_str_integer_only},
+    parsing::Parser,
+    resolving::Resolver,
+    token::{KeyWord, TokenType},
+};
+
+/// The REPL prompt unless `Interpreter::with_prompt` sets another.
+pub const DEFAULT_PROMPT: &str = "> ";
+/// Shown instead of the prompt while `feed_line` is waiting for the rest of an entry.
+pub const CONTINUATION_PROMPT: &str = "... ";
+
+/// Keeps one global environment alive across calls to `eval_source`, so later sources see the
+/// definitions of earlier ones.
+#[derive(Default)]
+pub struct Interpreter {
+    env: Environment,
+    options: ParseOptions,
+    /// The program being stepped through with `step_over` and `run_to`.
+    program: Vec<AstNode>,
+    next_statement: usize,
+    prompt: Option<String>,
+    /// Whether the last program run ended at a top-level `return`.
+    returned: bool,
+    /// Lines fed to `feed_line` that don't form a complete entry yet.
+    pending: String,
+}
+
+/// How `parse` turns source into a program; see the matching `Interpreter` builders.
+#[derive(Default)]
+pub(crate) struct ParseOptions {
+    pub(crate) sequence_expressions: bool,
+    pub(crate) max_errors: Option<usize>,
+    pub(crate) show_warnings: bool,
+    pub(crate) integer_only: bool,
+}
+
+impl Interpreter {
+    /// File mode: assigning to a variable that was never declared with `var` is an error.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// REPL mode: `x = 5` on an undeclared `x` defines it as a global instead of failing, so a
+    /// session doesn't need `var` for every new name.
+    pub fn repl() -> Self {
+        Self {
+            env: Environment::default().with_define_on_assign(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_output(
+        mut self,
+        output: impl Write + 'static,
+    ) -> Self {
+        self.env = std::mem::take(&mut self.env).with_output(output);
+        self
+    }
+
+    /// See `Parser::with_sequence_expressions`.
+    pub fn with_sequence_expressions(mut self) -> Self {
+        self.options.sequence_expressions = true;
+        self
+    }
+
+    /// See `Parser::with_max_errors`.
+    pub fn with_max_errors(
+        mut self,
+        max_errors: usize,
+    ) -> Self {
+        self.options.max_errors = Some(max_errors);
+        self
+    }
+
+    /// Prints the parser's warnings, such as "warning: statement has no effect", to stderr
+    /// before a program runs.
+    pub fn with_warnings(mut self) -> Self {
+        self.options.show_warnings = true;
+        self
+    }
+
+    /// A dialect without floats: float literals are a syntax error and arithmetic that would
+    /// produce a float fails with "floats are disabled". See `Environment::with_integer_only`.
+    pub fn with_integer_only(mut self) -> Self {
+        self.options.integer_only = true;
+        self.env = std::mem::take(&mut self.env).with_integer_only();
+        self
+    }
+
+    /// Replaces `DEFAULT_PROMPT` as the REPL's primary prompt.
+    pub fn with_prompt(
+        mut self,
+        prompt: &str,
+    ) -> Self {
+        self.prompt = Some(prompt.to_string());
+        self
+    }
+
+    /// See `Environment::with_saturating`.
+    pub fn with_saturating(mut self) -> Self {
+        self.env = std::mem::take(&mut self.env).with_saturating();
+        self
+    }
+
+    /// See `Environment::with_memoization`.
+    pub fn with_memoization(mut self) -> Self {
+        self.env = std::mem::take(&mut self.env).with_memoization();
+        self
+    }
+
+    /// See `Environment::with_trace`.
+    pub fn with_trace(mut self) -> Self {
+        self.env = std::mem::take(&mut self.env).with_trace();
+        self
+    }
+
+    pub fn with_step_budget(
+        mut self,
+        budget: usize,
+    ) -> Self {
+        self.env = std::mem::take(&mut self.env).with_step_budget(budget);
+        self
+    }
+
+    /// Makes a host-provided value visible to Lox programs as the global `name`, replacing any
+    /// existing global of that name.
+    pub fn define_global(
+        &mut self,
+        name: &str,
+        value: EvaluateResult,
+    ) {
+        self.env.define_global(name, value);
+    }
+
+    /// Exposes a Rust closure to Lox programs as the global function `name`. Calls with the
+    /// wrong number of arguments fail before `f` runs.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: usize,
+        f: impl Fn(&[EvaluateResult]) -> anyhow::Result<EvaluateResult> + 'static,
+    ) {
+        let function = NativeFunction::new(name, arity, move |_, arguments| f(arguments));
+        self.define_global(name, EvaluateResult::NativeFunction(function));
+    }
+
+    /// Overrides the binary `operator` for the operands `f` accepts; see `BinaryOperatorFn`.
+    pub fn define_operator(
+        &mut self,
+        operator: &str,
+        f: impl Fn(&EvaluateResult, &EvaluateResult) -> anyhow::Result<Option<EvaluateResult>> + 'static,
+    ) {
+        self.env.define_operator(operator, f);
+    }
+
+    /// Lexes, parses, resolves and evaluates `source`, returning the value of the last statement.
+    pub fn eval_source(
+        &mut self,
+        source: &str,
+    ) -> anyhow::Result<EvaluateResult> {
+        let ast = self.parse_source(source)?;
+        let (result, returned) = evaluate_until_return(&ast, &mut self.env)?;
+        self.returned = returned;
+        Ok(result)
+    }
+
+    /// Whether the last source, file or stepped program ended early at a `return`, whose value is
+    /// then the result. The binary uses an integer result as the exit code in that case.
+    pub fn returned(&self) -> bool {
+        self.returned
+    }
+
+    /// Runs the file at `path`. Its `import`s are resolved relative to its directory, and importing
+    /// it again, directly or through a cycle, is a no-op.
+    pub fn eval_file(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<EvaluateResult> {
+        let path = std::fs::canonicalize(path)?;
+        let source = std::fs::read_to_string(&path)?;
+        self.env.mark_imported(path.clone());
+        let previous = self.env.replace_current_file(Some(path));
+        let result = self.eval_source(&source);
+        self.env.replace_current_file(previous);
+        result
+    }
+
+    /// Parses `source` for stepping through one top-level statement at a time, replacing any
+    /// program loaded earlier. Nothing runs until `step_over` or `run_to`.
+    pub fn load(
+        &mut self,
+        source: &str,
+    ) -> anyhow::Result<()> {
+        self.program = self.parse_source(source)?;
+        self.next_statement = 0;
+        self.env.forget_memoized();
+        Ok(())
+    }
+
+    /// Index of the top-level statement of the loaded program that runs next; the program's
+    /// length once it has finished.
+    pub fn next_statement_index(&self) -> usize {
+        self.next_statement
+    }
+
+    /// Runs the next top-level statement, returning its value, or `None` when the program has
+    /// finished. A statement that fails is still stepped over.
+    pub fn step_over(&mut self) -> anyhow::Result<Option<EvaluateResult>> {
+        let Some(statement) = self.program.get(self.next_statement) else {
+            return Ok(None);
+        };
+        self.next_statement += 1;
+        let (result, returned) = caught_return(step(statement, &mut self.env), &mut self.env)?;
+        if returned {
+            self.next_statement = self.program.len();
+        }
+        self.returned = returned;
+        Ok(Some(result))
+    }
+
+    /// Runs statements until the one at `index` is next, so `run_to(2)` leaves the first two
+    /// executed. Does nothing if `index` has already been passed.
+    pub fn run_to(
+        &mut self,
+        index: usize,
+    ) -> anyhow::Result<()> {
+        while self.next_statement < index.min(self.program.len()) {
+            self.step_over()?;
+        }
+        Ok(())
+    }
+
+    /// Runs several files as one program against the same globals, so later files see what earlier
+    /// ones define. A top-level `return` in one file ends the program. Every file is parsed before
+    /// any of them runs, so a syntax error anywhere stops the whole program. Errors name the file
+    /// they came from.
+    pub fn eval_files(
+        &mut self,
+        paths: &[impl AsRef<Path>],
+    ) -> anyhow::Result<EvaluateResult> {
+        let mut programs = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let program = std::fs::read_to_string(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|source| self.parse_source(&source))
+                .with_context(|| format!("in {}", path.display()))?;
+            programs.push((std::fs::canonicalize(path)?, path, program));
+        }
+        let mut result = EvaluateResult::Nil;
+        for (canonical, path, program) in programs {
+            self.env.mark_imported(canonical.clone());
+            let previous = self.env.replace_current_file(Some(canonical));
+            let evaluated = evaluate_until_return(&program, &mut self.env);
+            self.env.replace_current_file(previous);
+            let returned;
+            (result, returned) = evaluated.with_context(|| format!("in {}", path.display()))?;
+            self.returned = returned;
+            if returned {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Evaluates one line typed at the REPL. When the line is a single bare expression such as
+    /// `1 + 2`, returns the `repr` of its value for the REPL to echo. The trailing `;` of an
+    /// expression statement may be left off at the prompt.
+    pub fn eval_line(
+        &mut self,
+        line: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let ast = match self.parse_source(line) {
+            Ok(ast) => ast,
+            Err(err) => self.parse_source(&format!("{};", line)).map_err(|_| err)?,
+        };
+        let value = evaluate_program(&ast, &mut self.env)?;
+        match ast.as_slice() {
+            [node] if is_bare_expression(node) => Ok(Some(value.repr())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Feeds one line typed at the REPL. While brackets are left open or the input ends in an
+    /// operator, the line is held back and `Ok(None)` returned; once the entry is complete it is
+    /// evaluated as one unit with `eval_line`.
+    pub fn feed_line(
+        &mut self,
+        line: &str,
+    ) -> anyhow::Result<Option<String>> {
+        self.pending.push_str(line);
+        if !self.pending.ends_with('\n') {
+            self.pending.push('\n');
+        }
+        if is_incomplete(&self.pending) {
+            return Ok(None);
+        }
+        let entry = std::mem::take(&mut self.pending);
+        self.eval_line(entry.trim_end())
+    }
+
+    /// The prompt to show before the next line: `CONTINUATION_PROMPT` in the middle of an entry.
+    pub fn prompt(&self) -> &str {
+        if !self.pending.is_empty() {
+            return CONTINUATION_PROMPT;
+        }
+        self.prompt.as_deref().unwrap_or(DEFAULT_PROMPT)
+    }
+
+    fn parse_source(
+        &self,
+        source: &str,
+    ) -> anyhow::Result<Vec<AstNode>> {
+        parse(source, &self.options)
+    }
+}
+
+/// Lexes, parses and resolves `source` into a program ready to evaluate.
+pub(crate) fn parse(
+    source: &str,
+    options: &ParseOptions,
+) -> anyhow::Result<Vec<AstNode>> {
+    let tokens = if options.integer_only {
+        lexing_str_integer_only(source)?
+    } else {
+        lexing_str(source)?
+    };
+    let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+    let mut parser = Parser::new(tokens);
+    if options.sequence_expressions {
+        parser = parser.with_sequence_expressions();
+    }
+    if let Some(max_errors) = options.max_errors {
+        parser = parser.with_max_errors(max_errors);
+    }
+    let mut ast = parser.parse()?;
+    if options.show_warnings {
+        for warning in parser.warnings() {
+            eprintln!("{}", warning);
+        }
+    }
+    Resolver::new().resolve(&mut ast);
+    Ok(ast)
+}
+
+/// Parses `source` with the default options and pretty-prints the resolved program, or the parse
+/// error, for comparing against a stored snapshot. The AST holds no maps, so the output is stable.
+pub fn parse_to_debug(source: &str) -> String {
+    match parse(source, &ParseOptions::default()) {
+        Ok(ast) => format!("{:#?}", ast),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+/// Whether `source` has more opening brackets than closing ones or ends in an operator that
+/// needs a right operand. Source that doesn't lex counts as complete so its error is reported.
+fn is_incomplete(source: &str) -> bool {
+    let Ok(tokens) = lexing_str(source) else { return false };
+    let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+    let depth = tokens.iter().fold(0i64, |depth, token| match token {
+        TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth + 1,
+        TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+        || matches!(
+            tokens.last(),
+            Some(
+                TokenType::Plus
+                    | TokenType::Minus
+                    | TokenType::Star
+                    | TokenType::StarStar
+                    | TokenType::Slash
+                    | TokenType::Percent
+                    | TokenType::Equal
+                    | TokenType::EqualEqual
+                    | TokenType::BangEqual
+                    | TokenType::Bang
+                    | TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual
+                    | TokenType::QuestionQuestion
+                    | TokenType::DotDot
+                    | TokenType::Dot
+                    | TokenType::Comma
+                    | TokenType::KeyWord(KeyWord::And | KeyWord::Or)
+            )
+        )
+}
+
+fn is_bare_expression(node: &AstNode) -> bool {
+    matches!(node, AstNode::ExprStmt(expr) if !matches!(**expr, AstNode::Assign { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    use super::{parse_to_debug, Interpreter, CONTINUATION_PROMPT, DEFAULT_PROMPT};
+    use crate::{evaluating::EvaluateResult, lexing::normalize_source, token::Number};
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(
+            &mut self,
+            buf: &[u8],
+        ) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn undeclared_assignment() {
+        let mut repl = Interpreter::repl();
+        repl.eval_source("x = 5;").unwrap();
+        assert_eq!(repl.eval_source("x + 1;").unwrap(), EvaluateResult::Number(Number::Integer(6)));
+
+        let mut file = Interpreter::new();
+        let err = file.eval_source("x = 5;").unwrap_err();
+        assert_eq!(err.to_string(), "Undefined variable x");
+    }
+
+    #[test]
+    fn print_group() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.eval_source("print (1 + 2);").unwrap();
+        assert_eq!(output.text(), "3\n");
+    }
+
+    #[test]
+    fn reassign_different_type() {
+        let mut interpreter = Interpreter::new();
+        let result = interpreter.eval_source("var x = 1;\nx = \"hello\";\nx;").unwrap();
+        assert_eq!(result, EvaluateResult::String("hello".to_string()));
+    }
+
+    #[test]
+    fn step_budget() {
+        let mut interpreter = Interpreter::new().with_step_budget(1000);
+        let err = interpreter.eval_source("while true {}").unwrap_err();
+        assert_eq!(err.to_string(), "execution budget exceeded");
+    }
+
+    #[test]
+    fn repl_echo() {
+        let output = Output::default();
+        let mut repl = Interpreter::repl().with_output(output.clone());
+        assert_eq!(repl.eval_line("1 + 2").unwrap(), Some("3".to_string()));
+        assert_eq!(repl.eval_line("\"a\" + \"b\";").unwrap(), Some("\"ab\"".to_string()));
+        assert_eq!(repl.eval_line("y = 2").unwrap(), None);
+        assert_eq!(repl.eval_line("var x = 1;").unwrap(), None);
+        assert_eq!(repl.eval_line("print x;").unwrap(), None);
+        assert_eq!(output.text(), "1\n");
+        assert_eq!(repl.eval_line("").unwrap(), None);
+
+        // A single-token line is an expression too.
+        assert_eq!(repl.eval_line("var a = 5;").unwrap(), None);
+        assert_eq!(repl.eval_line("a").unwrap(), Some("5".to_string()));
+        assert_eq!(repl.eval_line("7").unwrap(), Some("7".to_string()));
+        assert_eq!(repl.eval_line("missing").unwrap_err().to_string(), "Undefined variable missing");
+    }
+
+    #[test]
+    fn uninitialized_and_undeclared() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.eval_source("var x;\nprint x;").unwrap();
+        assert_eq!(output.text(), "nil\n");
+
+        let err = interpreter.eval_source("print y;").unwrap_err();
+        assert_eq!(err.to_string(), "Undefined variable y");
+    }
+
+    #[test]
+    fn sequence_expressions() {
+        let mut interpreter = Interpreter::new().with_sequence_expressions();
+        assert_eq!(interpreter.eval_source("(1, 2, 3);").unwrap(), EvaluateResult::Number(Number::Integer(3)));
+        assert_eq!(
+            interpreter.eval_source("indexOf(\"abc\", (\"x\", \"c\"));").unwrap(),
+            EvaluateResult::Number(Number::Integer(2))
+        );
+
+        let err = Interpreter::new().eval_source("(1, 2, 3);").unwrap_err();
+        assert_eq!(err.to_string(), "Expected ')' after expression");
+    }
+
+    #[test]
+    fn define_global() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.define_global("VERSION", EvaluateResult::String("1.0".to_string()));
+        interpreter.eval_source("print \"lox \" + VERSION;").unwrap();
+        assert_eq!(output.text(), "lox 1.0\n");
+    }
+
+    #[test]
+    fn register_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("double", 1, |arguments| match arguments {
+            [EvaluateResult::Number(Number::Integer(n))] => Ok(EvaluateResult::Number(Number::Integer(n * 2))),
+            _ => anyhow::bail!("double expects an integer"),
+        });
+        assert_eq!(
+            interpreter.eval_source("double(21);").unwrap(),
+            EvaluateResult::Number(Number::Integer(42))
+        );
+
+        let err = interpreter.eval_source("double(1, 2);").unwrap_err();
+        assert_eq!(err.to_string(), "double expected 1 arguments but got 2");
+    }
+
+    #[test]
+    fn evaluation_order() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let seen = order.clone();
+        interpreter.register_fn("tick", 1, move |arguments| {
+            seen.borrow_mut().push(arguments[0].clone());
+            Ok(arguments[0].clone())
+        });
+
+        interpreter.eval_source("tick(1) - tick(2) * tick(3);").unwrap();
+        interpreter.eval_source("format(\"{} {}\", tick(4), tick(5));").unwrap();
+        interpreter.eval_source("[tick(6), tick(7)];").unwrap();
+        let expected = (1..=7).map(|n| EvaluateResult::Number(Number::Integer(n))).collect::<Vec<_>>();
+        assert_eq!(*order.borrow(), expected);
+    }
+
+    #[test]
+    fn constants() {
+        let mut interpreter = Interpreter::new();
+        let err = interpreter.eval_source("const LIMIT = 10;\nLIMIT = 11;").unwrap_err();
+        assert_eq!(err.to_string(), "cannot reassign to constant LIMIT");
+        assert_eq!(interpreter.eval_source("LIMIT;").unwrap(), EvaluateResult::Number(Number::Integer(10)));
+
+        let err = interpreter.eval_source("{ const inner = 1; inner = 2; }").unwrap_err();
+        assert_eq!(err.to_string(), "cannot reassign to constant inner");
+
+        assert_eq!(
+            interpreter.eval_source("var count = 1;\ncount = 2;\ncount;").unwrap(),
+            EvaluateResult::Number(Number::Integer(2))
+        );
+    }
+
+    #[test]
+    fn destructuring() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_source("var pair = [1, \"two\"];\nvar [a, b] = pair;").unwrap();
+        assert_eq!(interpreter.eval_source("a;").unwrap(), EvaluateResult::Number(Number::Integer(1)));
+        assert_eq!(interpreter.eval_source("b;").unwrap(), EvaluateResult::String("two".to_string()));
+
+        let err = interpreter.eval_source("var [x, y] = [1, 2, 3];").unwrap_err();
+        assert_eq!(err.to_string(), "Cannot destructure a list of 3 elements into 2 names");
+        let err = interpreter.eval_source("var [x, y] = 1;").unwrap_err();
+        assert_eq!(err.to_string(), "Can only destructure lists, got number");
+    }
+
+    #[test]
+    fn define_operator() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_operator("+", |left, right| match (left, right) {
+            (EvaluateResult::List(left), EvaluateResult::List(right)) => {
+                Ok(Some(EvaluateResult::list([left.borrow().clone(), right.borrow().clone()].concat())))
+            }
+            _ => Ok(None),
+        });
+        assert_eq!(
+            interpreter.eval_source("[1] + [2, 3];").unwrap(),
+            interpreter.eval_source("[1, 2, 3];").unwrap()
+        );
+        assert_eq!(interpreter.eval_source("1 + 2;").unwrap(), EvaluateResult::Number(Number::Integer(3)));
+        assert!(interpreter.eval_source("[1] - [2];").is_err());
+    }
+
+    #[test]
+    fn import() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.eval_file("tests/import/main.lox").unwrap();
+        assert_eq!(output.text(), "hello, world\n");
+
+        let err = Interpreter::new().eval_source("import \"tests/import/missing.lox\";").unwrap_err();
+        assert_eq!(err.to_string(), "Cannot import tests/import/missing.lox");
+    }
+
+    #[test]
+    fn memoization() {
+        for (interpreter, expected) in [(Interpreter::new(), 3), (Interpreter::new().with_memoization(), 1)] {
+            let mut interpreter = interpreter;
+            let multiplications = Rc::new(RefCell::new(0));
+            let counter = multiplications.clone();
+            interpreter.define_operator("*", move |_, _| {
+                *counter.borrow_mut() += 1;
+                Ok(None)
+            });
+            let result = interpreter
+                .eval_source("var total = 0;\nfor x in [1, 2, 3] { total = total + 2 * 3; }\ntotal;")
+                .unwrap();
+            assert_eq!(result, EvaluateResult::Number(Number::Integer(18)));
+            assert_eq!(*multiplications.borrow(), expected);
+        }
+    }
+
+    #[test]
+    fn memoized_loops_in_a_row() {
+        let source = "var k = 0; var y = 0; while k < 3 { y = y + (2 * 3); k = k + 1; }\n\
+                      var m = 0; while m < 2 { y = y + (5 - 1); m = m + 1; } y;";
+        for interpreter in [Interpreter::new(), Interpreter::new().with_memoization()] {
+            let mut interpreter = interpreter;
+            assert_eq!(interpreter.eval_source(source).unwrap(), EvaluateResult::Number(Number::Integer(26)));
+        }
+    }
+
+    #[test]
+    fn write_without_newline() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.eval_source("write(1);\nwrite(\"a\");\nprint \"!\";\nwrite(2);").unwrap();
+        assert_eq!(output.text(), "1a!\n2");
+    }
+
+    #[test]
+    fn sprint() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let source = "var x = [1, \"a\", nil, 2.5];";
+        interpreter.eval_source(source).unwrap();
+        let sprinted = interpreter.eval_source("sprint(x);").unwrap();
+        assert!(output.text().is_empty());
+        interpreter.eval_source("print x;").unwrap();
+        assert_eq!(sprinted, EvaluateResult::String(output.text().trim_end_matches('\n').to_string()));
+
+        assert_eq!(
+            interpreter.eval_source("sprint(1, \"two\", true);").unwrap(),
+            EvaluateResult::String("1 two true".to_string())
+        );
+        assert_eq!(interpreter.eval_source("sprint();").unwrap(), EvaluateResult::String(String::new()));
+    }
+
+    #[test]
+    fn if_expression() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let source = "
+            var n = 5;
+            var size = if n > 3 { var half = n / 2; half * 10 } else { 0 };
+            var sign = if n < 0 { \"negative\" } else if n == 0 { \"zero\" } else { \"positive\" };
+            print size;
+            print sign;
+            n = -1;
+            print if n > 3 { 1 } else { 2 };
+        ";
+        interpreter.eval_source(&normalize_source(source)).unwrap();
+        assert_eq!(output.text(), "20\npositive\n2\n");
+
+        let err = interpreter.eval_source("var x = if true { 1 };").unwrap_err();
+        assert_eq!(err.to_string(), "Expected 'else' in if expression");
+    }
+
+    #[test]
+    fn global_declaration() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let source = "
+            var x = 1;
+            {
+                var x = 2;
+                global x = x + 3;
+                global created = \"new\";
+                print x;
+            }
+            print x;
+            print created;
+        ";
+        interpreter.eval_source(&normalize_source(source)).unwrap();
+        assert_eq!(output.text(), "2\n5\nnew\n");
+
+        let err = interpreter.eval_source("const limit = 1;\n{ global limit = 2; }").unwrap_err();
+        assert_eq!(err.to_string(), "cannot reassign to constant limit");
+    }
+
+    #[test]
+    fn eval_files() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.eval_files(&["tests/files/first.lox", "tests/files/second.lox"]).unwrap();
+        assert_eq!(output.text(), "hello, world\n");
+
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let err = interpreter
+            .eval_files(&["tests/files/first.lox", "tests/files/undefined.lox"])
+            .unwrap_err();
+        assert_eq!(format!("{:#}", err), "in tests/files/undefined.lox: Undefined variable missing");
+        assert_eq!(output.text(), "hello\n");
+
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let err = interpreter.eval_files(&["tests/files/second.lox", "tests/files/syntax.lox"]).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "in tests/files/syntax.lox: Expected ';' after expression in print statement"
+        );
+        assert_eq!(output.text(), "");
+    }
+
+    #[test]
+    fn printf() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter
+            .eval_source("printf(\"{:.2}\", 3.14159);\nprintf(\"{:3}: {:6.1}\", 7, 12.345);")
+            .unwrap();
+        assert_eq!(output.text(), "3.14\n  7:   12.3\n");
+    }
+
+    #[test]
+    fn stepping() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter.load("var a = 1;\nvar b = a + 1;\nprint b;\nb = 10;").unwrap();
+        interpreter.run_to(2).unwrap();
+        assert_eq!(interpreter.next_statement_index(), 2);
+        assert_eq!(interpreter.eval_source("a + b;").unwrap(), EvaluateResult::Number(Number::Integer(3)));
+        assert_eq!(output.text(), "");
+
+        assert_eq!(interpreter.step_over().unwrap(), Some(EvaluateResult::Number(Number::Integer(2))));
+        assert_eq!(output.text(), "2\n");
+        interpreter.run_to(1).unwrap();
+        assert_eq!(interpreter.next_statement_index(), 3);
+        interpreter.run_to(usize::MAX).unwrap();
+        assert_eq!(interpreter.next_statement_index(), 4);
+        assert_eq!(interpreter.step_over().unwrap(), None);
+        assert_eq!(interpreter.eval_source("b;").unwrap(), EvaluateResult::Number(Number::Integer(10)));
+    }
+
+    #[test]
+    fn comparisons_without_panics() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_global("NaN", EvaluateResult::Number(Number::Float(f64::NAN)));
+        assert_eq!(interpreter.eval_source("NaN > 1;").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(interpreter.eval_source("NaN <= NaN;").unwrap(), EvaluateResult::Boolean(false));
+        assert_eq!(interpreter.eval_source("1 < 2.0;").unwrap(), EvaluateResult::Boolean(true));
+        assert_eq!(interpreter.eval_source("3 >= 2.5;").unwrap(), EvaluateResult::Boolean(true));
+
+        let err = interpreter.eval_source("1 < \"2\";").unwrap_err();
+        assert_eq!(err.to_string(), "cannot compare number and string");
+    }
+
+    #[test]
+    fn defer() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter
+            .eval_source("{\n    defer print \"first\";\n    defer print \"second\";\n    print \"body\";\n}\nprint \"after\";")
+            .unwrap();
+        assert_eq!(output.text(), "body\nsecond\nfirst\nafter\n");
+
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let err = interpreter.eval_source("{ defer print \"cleanup\"; print 1 / 0; }").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        assert_eq!(output.text(), "cleanup\n");
+    }
+
+    #[test]
+    fn unless_until() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter
+            .eval_source("unless false print 1;\nunless true print 2; else print 3;")
+            .unwrap();
+        interpreter.eval_source("var i = 0;\nuntil i >= 3 { print i; i = i + 1; }").unwrap();
+        assert_eq!(output.text(), "1\n3\n0\n1\n2\n");
+    }
+
+    #[test]
+    fn integer_only() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_integer_only().with_output(output.clone());
+        interpreter.eval_source("print 7 / 2;\nprint 2 ** 10 - 7 % 4;").unwrap();
+        assert_eq!(output.text(), "3\n1021\n");
+
+        let err = interpreter.eval_source("print 1.5;").unwrap_err();
+        assert_eq!(err.to_string(), "floats are disabled");
+        let err = interpreter.eval_source("print 2 ** -1;").unwrap_err();
+        assert_eq!(err.to_string(), "floats are disabled");
+    }
+
+    #[test]
+    fn parse_snapshot() {
+        let snapshot = parse_to_debug("var a = 1;\n{ a = a + 2.5; print -a; }");
+        assert_eq!(format!("{}\n", snapshot), include_str!("../tests/parse_snapshot.txt"));
+        assert_eq!(parse_to_debug("var a = ;"), "error: Expected expression in parsing primary");
+    }
+
+    #[test]
+    fn multiline_entry() {
+        let output = Output::default();
+        let mut repl = Interpreter::repl().with_prompt("lox> ").with_output(output.clone());
+        assert_eq!(repl.prompt(), "lox> ");
+
+        // The block isn't run until the line closing its brace arrives.
+        assert_eq!(repl.feed_line("{ var a = 1;\n").unwrap(), None);
+        assert_eq!(repl.prompt(), CONTINUATION_PROMPT);
+        assert_eq!(output.text(), "");
+        assert_eq!(repl.feed_line("print a + 1; }\n").unwrap(), None);
+        assert_eq!(output.text(), "2\n");
+        assert_eq!(repl.prompt(), "lox> ");
+
+        assert_eq!(repl.feed_line("1 +").unwrap(), None);
+        assert_eq!(repl.feed_line("2").unwrap(), Some("3".to_string()));
+        assert_eq!(Interpreter::repl().prompt(), DEFAULT_PROMPT);
+    }
+
+    #[test]
+    fn group_evaluated_once() {
+        let modes: [fn(Interpreter) -> Interpreter; 3] = [|i| i, Interpreter::with_trace, Interpreter::with_memoization];
+        for mode in modes {
+            let calls = Rc::new(RefCell::new(0));
+            let mut interpreter = mode(Interpreter::new().with_output(Output::default()));
+            let counter = calls.clone();
+            interpreter.register_fn("tick", 0, move |_| {
+                *counter.borrow_mut() += 1;
+                Ok(EvaluateResult::Number(Number::Integer(*counter.borrow())))
+            });
+            let result = interpreter.eval_source("print ((tick())) + (tick() * 10);").unwrap();
+            assert_eq!(result, EvaluateResult::Number(Number::Integer(21)));
+            assert_eq!(*calls.borrow(), 2);
+        }
+    }
+
+    #[test]
+    fn saturating() {
+        let mut interpreter = Interpreter::new().with_saturating();
+        let max = EvaluateResult::Number(Number::Integer(i64::MAX));
+        let min = EvaluateResult::Number(Number::Integer(i64::MIN));
+        assert_eq!(interpreter.eval_source("9223372036854775807 + 1;").unwrap(), max);
+        assert_eq!(interpreter.eval_source("-9223372036854775807 - 2;").unwrap(), min);
+        assert_eq!(interpreter.eval_source("3037000500 * 3037000500;").unwrap(), max);
+        assert_eq!(interpreter.eval_source("2 ** 100;").unwrap(), max);
+        assert_eq!(interpreter.eval_source("1 / 0;").unwrap_err().to_string(), "Division by zero");
+
+        // Without the mode the same overflows are errors.
+        let mut interpreter = Interpreter::new();
+        let err = interpreter.eval_source("9223372036854775807 + 1;").unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in 9223372036854775807 + 1");
+        let err = interpreter.eval_source("-(-9223372036854775807 - 1);").unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in -(-9223372036854775808)");
+        assert_eq!(interpreter.eval_source("1 + 2.5;").unwrap(), EvaluateResult::Number(Number::Float(3.5)));
+    }
+
+    #[test]
+    fn top_level_return() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        let result = interpreter
+            .eval_source("print 1;\n{ defer print \"cleanup\"; return 2; }\nprint 3;")
+            .unwrap();
+        assert_eq!(result, EvaluateResult::Number(Number::Integer(2)));
+        assert!(interpreter.returned());
+        assert_eq!(output.text(), "1\ncleanup\n");
+
+        assert_eq!(
+            crate::run("var i = 0;\nwhile true { i = i + 1; if i == 5 return; }\nprint i;").unwrap(),
+            EvaluateResult::Nil
+        );
+        interpreter.eval_source("1 + 1;").unwrap();
+        assert!(!interpreter.returned());
+
+        interpreter.load("return 4;\nprint 5;").unwrap();
+        assert_eq!(interpreter.step_over().unwrap(), Some(EvaluateResult::Number(Number::Integer(4))));
+        assert_eq!(interpreter.step_over().unwrap(), None);
+    }
+
+    #[test]
+    fn break_continue() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter
+            .eval_source(
+                "outer: for i in [1, 2, 3] {\n  for j in [1, 2, 3] {\n    if j == 2 continue;\n    if i == 2 continue outer;\n    if i == 3 break outer;\n    print i * 10 + j;\n  }\n}\nprint \"done\";",
+            )
+            .unwrap();
+        assert_eq!(output.text(), "11\n13\ndone\n");
+
+        let output = Output::default();
+        let mut interpreter = Interpreter::new().with_output(output.clone());
+        interpreter
+            .eval_source("var i = 0;\nwhile true { i = i + 1; if i == 2 continue; if i > 3 break; print i; }\nrepeat 3 { break; }")
+            .unwrap();
+        assert_eq!(output.text(), "1\n3\n");
+
+        let err = crate::run("break;").unwrap_err();
+        assert_eq!(err.to_string(), "'break' outside of a loop");
+        let err = crate::run("while true { continue missing; }").unwrap_err();
+        assert_eq!(err.to_string(), "'continue missing' outside of a loop labeled 'missing'");
+        let err = crate::run("outer: print 1;").unwrap_err();
+        assert_eq!(err.to_string(), "Expected a loop after label 'outer'");
+    }
+}
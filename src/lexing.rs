@@ -6,9 +6,156 @@ use crate::token::{KeyWord, Number, TokenType};
 
 pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
     let content = std::fs::read_to_string(path)?;
+    lexing_str(&content)
+}
+
+/// Undoes the indentation of a program embedded in a Rust string literal: drops a leading blank
+/// line, then strips the indentation common to every non-blank line. Blank lines are emptied,
+/// so the closing line of an indented literal doesn't leave stray whitespace behind.
+pub fn normalize_source(source: &str) -> String {
+    let source = match source.split_once('\n') {
+        Some((first, rest)) if first.trim().is_empty() => rest,
+        _ => source,
+    };
+    let indent = source
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    source
+        .split('\n')
+        .map(|line| if line.trim().is_empty() { "" } else { &line[indent..] })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Writes `tokens` back out as source that lexes to the same tokens. Whitespace tokens are copied;
+/// between two other tokens a space goes in only where writing them together would lex
+/// differently, as with `var x`, `= =` or `/ /`. Comments don't survive lexing, so they are lost.
+pub fn tokens_to_source(tokens: &[TokenType]) -> String {
+    let mut source = String::new();
+    let mut previous: Option<&TokenType> = None;
+    for token in tokens {
+        if let Some(previous) = previous {
+            if !previous.is_skippable() && !token.is_skippable() && !lexes_apart(previous, token) {
+                source.push(' ');
+            }
+        }
+        source.push_str(&token_source(token));
+        previous = Some(token);
+    }
+    source
+}
+
+fn token_source(token: &TokenType) -> String {
+    match token {
+        TokenType::String(s) => string_literal(s),
+        TokenType::Number(number) => number_literal(*number),
+        other => other.to_string(),
+    }
+}
+
+fn lexes_apart(
+    first: &TokenType,
+    second: &TokenType,
+) -> bool {
+    let joined = token_source(first) + &token_source(second);
+    lexing_str(&joined).is_ok_and(|tokens| tokens.len() == 2 && &tokens[0] == first && &tokens[1] == second)
+}
+
+/// Quotes `s`, escaping whatever the lexer would otherwise read differently.
+pub(crate) fn string_literal(s: &str) -> String {
+    let mut literal = String::from('"');
+    for c in s.chars() {
+        match c {
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            '\r' => literal.push_str("\\r"),
+            '\0' => literal.push_str("\\0"),
+            '"' | '\\' | '$' => {
+                literal.push('\\');
+                literal.push(c);
+            }
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// A literal that lexes back to `number`: a whole float keeps an `f` suffix, so `2.0` doesn't come
+/// back as the integer `2`.
+pub(crate) fn number_literal(number: Number) -> String {
+    match number {
+        Number::Integer(i) => i.to_string(),
+        Number::Float(f) if f.to_string().contains('.') => f.to_string(),
+        Number::Float(f) => format!("{}f", f),
+    }
+}
+
+/// Scans Lox source into tokens.
+///
+/// Inside string literals a backslash starts an escape: `\n`, `\t`, `\r`, `\0`, `\"`, `\\`, and `\$`
+/// for a literal `$` (reserved for string interpolation). A backslash immediately followed by a
+/// newline is a line continuation: both characters are dropped, so a long literal can be split
+/// across source lines. Any other character after a backslash is an error.
+///
+/// A number literal may end in a type suffix: `3f` is the float `3.0` and `3i` the integer `3`.
+/// An `i` suffix on a literal with a fractional part, such as `3.5i`, is an error.
+///
+/// An unsuffixed integer literal too large for `i64`, such as `99999999999999999999`, becomes
+/// the nearest float instead of failing; with an `i` suffix it is an error.
+pub fn lexing_str(content: &str) -> anyhow::Result<Vec<TokenType>> {
+    scan(content, false, &mut 0)
+}
+
+/// Like `lexing_str`, but for the integer-only dialect: a float literal such as `2.5` or `3f`,
+/// and an integer literal too large for `i64`, is an error instead of a float.
+pub fn lexing_str_integer_only(content: &str) -> anyhow::Result<Vec<TokenType>> {
+    scan(content, true, &mut 0)
+}
+
+/// Counts over a source, e.g. for an editor status bar.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LexStats {
+    pub lines: usize,
+    /// Tokens other than whitespace.
+    pub tokens: usize,
+    pub comments: usize,
+    pub strings: usize,
+    pub numbers: usize,
+}
+
+/// Lexes `source` and counts its lines, tokens, comments and literals. Fails where `lexing_str`
+/// would.
+pub fn lex_stats(source: &str) -> anyhow::Result<LexStats> {
+    let mut stats = LexStats {
+        lines: source.lines().count(),
+        ..LexStats::default()
+    };
+    for token in scan(source, false, &mut stats.comments)? {
+        match token {
+            TokenType::String(_) => stats.strings += 1,
+            TokenType::Number(_) => stats.numbers += 1,
+            _ if token.is_skippable() => continue,
+            _ => {}
+        }
+        stats.tokens += 1;
+    }
+    Ok(stats)
+}
+
+/// Adds every `//` comment it skips to `comments`.
+fn scan(
+    content: &str,
+    integer_only: bool,
+    comments: &mut usize,
+) -> anyhow::Result<Vec<TokenType>> {
     let mut iter = content.chars().peekable();
 
-    let mut vec = Vec::new();
+    // Whitespace is tokenized too, so real sources average around two bytes per token.
+    let mut vec = Vec::with_capacity(content.len() / 2);
     while let Some(&c) = iter.peek() {
         match c {
             '=' => {
@@ -18,6 +165,10 @@ pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
                         iter.next();
                         vec.push(TokenType::EqualEqual);
                     }
+                    Some('>') => {
+                        iter.next();
+                        vec.push(TokenType::FatArrow);
+                    }
                     _ => vec.push(TokenType::Equal),
                 }
             }
@@ -51,11 +202,42 @@ pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
                     _ => vec.push(TokenType::Less),
                 }
             }
+            '.' => {
+                iter.next();
+                match iter.peek() {
+                    Some('.') => {
+                        iter.next();
+                        vec.push(TokenType::DotDot);
+                    }
+                    _ => vec.push(TokenType::Dot),
+                }
+            }
+            '*' => {
+                iter.next();
+                match iter.peek() {
+                    Some('*') => {
+                        iter.next();
+                        vec.push(TokenType::StarStar);
+                    }
+                    _ => vec.push(TokenType::Star),
+                }
+            }
+            '?' => {
+                iter.next();
+                match iter.peek() {
+                    Some('?') => {
+                        iter.next();
+                        vec.push(TokenType::QuestionQuestion);
+                    }
+                    _ => bail!("Expected '?' after '?'"),
+                }
+            }
             '/' => {
                 iter.next();
                 match iter.peek() {
                     Some('/') => {
                         iter.next();
+                        *comments += 1;
                         while let Some(&c) = iter.peek() {
                             if c == '\n' {
                                 break;
@@ -77,8 +259,24 @@ pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
                         valid = true;
                         break;
                     }
-                    string_literal.push(c);
                     iter.next();
+                    if c == '\\' {
+                        let escaped = match iter.next() {
+                            Some('\n') => continue,
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            Some('$') => '$',
+                            Some(other) => bail!("Invalid escape sequence \\{}", other),
+                            None => break,
+                        };
+                        string_literal.push(escaped);
+                        continue;
+                    }
+                    string_literal.push(c);
                 }
                 if iter.peek().is_none() && !valid {
                     bail!("UnterminatedString");
@@ -92,6 +290,12 @@ pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
                         number.push(c);
                         iter.next();
                     } else if c == '.' {
+                        let mut ahead = iter.clone();
+                        ahead.next();
+                        if ahead.peek() == Some(&'.') {
+                            // `0..10` is a range, not a float.
+                            break;
+                        }
                         if is_float {
                             bail!("DoubleDot");
                         }
@@ -102,10 +306,29 @@ pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
                         break;
                     }
                 }
+                let mut ahead = iter.clone();
+                let suffix = ahead.next().filter(|&c| c == 'f' || c == 'i');
+                let mut integer_suffix = false;
+                if suffix.is_some() && !ahead.peek().is_some_and(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                    iter.next();
+                    match suffix {
+                        Some('i') if is_float => bail!("Integer suffix on float literal {}", number),
+                        Some('f') => is_float = true,
+                        _ => integer_suffix = true,
+                    }
+                }
+                if is_float && integer_only {
+                    bail!("floats are disabled")
+                }
                 if is_float {
                     vec.push(TokenType::Number(Number::Float(number.parse::<f64>().context("Parse Error")?)));
                 } else {
-                    vec.push(TokenType::Number(Number::Integer(number.parse::<i64>().context("Parse Error")?)));
+                    let value = match number.parse::<i64>() {
+                        Ok(i) => Number::Integer(i),
+                        Err(_) if integer_suffix || integer_only => bail!("Integer literal {} is out of range", number),
+                        Err(_) => Number::Float(number.parse::<f64>().context("Parse Error")?),
+                    };
+                    vec.push(TokenType::Number(value));
                 }
             }
             c if c.is_ascii_alphabetic() || c == '_' => {
@@ -147,10 +370,116 @@ pub fn lexing(path: &str) -> anyhow::Result<Vec<TokenType>> {
 
 #[cfg(test)]
 mod tests {
+    use crate::token::{Number, TokenType};
+
     #[test]
     fn test_scanning() {
         let path = "tests/scan.lox";
         let tokens = super::lexing(path).unwrap();
         println!("{:?}", tokens);
     }
+
+    #[test]
+    fn string_line_continuation() {
+        let tokens = super::lexing_str("\"hello, \\\nworld\"").unwrap();
+        assert_eq!(tokens, vec![TokenType::String("hello, world".to_string())]);
+    }
+
+    #[test]
+    fn number_suffixes() {
+        assert_eq!(super::lexing_str("3f").unwrap(), vec![TokenType::Number(Number::Float(3.0))]);
+        assert_eq!(super::lexing_str("3i").unwrap(), vec![TokenType::Number(Number::Integer(3))]);
+        assert_eq!(super::lexing_str("2.5f").unwrap(), vec![TokenType::Number(Number::Float(2.5))]);
+
+        let err = super::lexing_str("3.5i").unwrap_err();
+        assert_eq!(err.to_string(), "Integer suffix on float literal 3.5");
+    }
+
+    #[test]
+    fn string_escapes() {
+        let tokens = super::lexing_str(r#""a\nb\tc\rd\0e\"f\\g\$h""#).unwrap();
+        assert_eq!(tokens, vec![TokenType::String("a\nb\tc\rd\0e\"f\\g$h".to_string())]);
+
+        let err = super::lexing_str(r#""\q""#).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid escape sequence \\q");
+    }
+
+    #[test]
+    fn huge_integer() {
+        assert_eq!(
+            super::lexing_str("99999999999999999999").unwrap(),
+            vec![TokenType::Number(Number::Float(1e20))]
+        );
+        assert_eq!(
+            super::lexing_str("9223372036854775807").unwrap(),
+            vec![TokenType::Number(Number::Integer(i64::MAX))]
+        );
+
+        let err = super::lexing_str("99999999999999999999i").unwrap_err();
+        assert_eq!(err.to_string(), "Integer literal 99999999999999999999 is out of range");
+    }
+
+    #[test]
+    fn normalize_source() {
+        let embedded = "
+            var a = 1;
+            {
+                print a;
+            }
+        ";
+        let flush_left = "var a = 1;\n{\n    print a;\n}\n";
+        assert_eq!(super::normalize_source(embedded), flush_left);
+        assert_eq!(
+            super::lexing_str(&super::normalize_source(embedded)).unwrap(),
+            super::lexing_str(flush_left).unwrap()
+        );
+        assert_eq!(super::normalize_source(flush_left), flush_left);
+    }
+
+    #[test]
+    fn integer_only() {
+        assert_eq!(super::lexing_str_integer_only("7 / 2").unwrap(), super::lexing_str("7 / 2").unwrap());
+        for source in ["2.5", "3f", "1.0"] {
+            assert_eq!(super::lexing_str_integer_only(source).unwrap_err().to_string(), "floats are disabled");
+        }
+        let err = super::lexing_str_integer_only("99999999999999999999").unwrap_err();
+        assert_eq!(err.to_string(), "Integer literal 99999999999999999999 is out of range");
+    }
+
+    #[test]
+    fn tokens_to_source() {
+        let source = "var x=1 ..10;// comment\nif x>=2.0 and !(y==\"a\\\"b\\n\") print 3i**2/ 4.5;\nvar [a, b] = [1f, 2];";
+        let tokens = super::lexing_str(source).unwrap();
+        assert_eq!(super::lexing_str(&super::tokens_to_source(&tokens)).unwrap(), tokens);
+
+        let significant = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let compact = super::tokens_to_source(&significant);
+        assert_eq!(compact, "var x=1..10;if x>=2f and!(y==\"a\\\"b\\n\")print 3**2/4.5;var[a,b]=[1f,2];");
+        let relexed = super::lexing_str(&compact)
+            .unwrap()
+            .into_iter()
+            .filter(|token| !token.is_skippable())
+            .collect::<Vec<TokenType>>();
+        assert_eq!(relexed, significant);
+        assert_eq!(
+            super::tokens_to_source(&[TokenType::Equal, TokenType::Equal, TokenType::Slash, TokenType::Slash]),
+            "= =/ /"
+        );
+    }
+
+    #[test]
+    fn lex_stats() {
+        let source = "// greeting\nvar name = \"lox\"; // inline\nprint \"hello \" + name;\n\nprint 1 + 2.5;\n";
+        assert_eq!(
+            super::lex_stats(source).unwrap(),
+            super::LexStats {
+                lines: 5,
+                tokens: 15,
+                comments: 2,
+                strings: 2,
+                numbers: 2,
+            }
+        );
+        assert_eq!(super::lex_stats("").unwrap(), super::LexStats::default());
+    }
 }
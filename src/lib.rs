@@ -0,0 +1,23 @@
+pub mod ast;
+pub mod builtins;
+pub mod environment;
+pub mod evaluating;
+pub mod interpreting;
+pub mod lexing;
+pub mod parsing;
+pub mod resolving;
+pub mod statement;
+pub mod token;
+
+use evaluating::EvaluateResult;
+use interpreting::Interpreter;
+
+/// Lexes, parses, resolves and evaluates `source`, returning the value of the last statement.
+pub fn run(source: &str) -> anyhow::Result<EvaluateResult> {
+    Interpreter::new().eval_source(source)
+}
+
+/// Runs the files at `paths`, in order, as one program; see `Interpreter::eval_files`.
+pub fn run_files(paths: &[&str]) -> anyhow::Result<EvaluateResult> {
+    Interpreter::new().eval_files(paths)
+}
@@ -1,8 +1,136 @@
-mod ast;
-mod evaluating;
-mod lexing;
-mod parsing;
-mod statement;
-mod token;
-
-fn main() {}
+use std::io::{IsTerminal, Read, Write};
+
+use anyhow::{bail, Context};
+use lox::{evaluating::EvaluateResult, interpreting::Interpreter, token::Number};
+
+const USAGE: &str = "Usage: lox [OPTIONS] [FILE]
+
+Runs FILE, or starts a REPL when no file is given.
+
+Options:
+  -, --stdin     Read the program from stdin
+      --trace    Log every evaluated node and its result to stderr
+      --color, --no-color
+                 Force colored error messages on or off (default: on when stderr is a terminal)
+      --max-errors N
+                 Stop reporting syntax errors after N of them (default 20)
+  -h, --help     Print this help
+  -V, --version  Print the version";
+
+fn main() {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    let color = match args.iter().rev().find(|arg| *arg == "--color" || *arg == "--no-color") {
+        Some(flag) => flag == "--color",
+        None => std::io::stderr().is_terminal(),
+    };
+    args.retain(|arg| arg != "--color" && arg != "--no-color");
+    if let Err(err) = run(args, color) {
+        eprintln!("{}", render_error(&err, color));
+        std::process::exit(1);
+    }
+}
+
+fn run(
+    mut args: Vec<String>,
+    color: bool,
+) -> anyhow::Result<()> {
+    let trace = args.iter().any(|arg| arg == "--trace");
+    args.retain(|arg| arg != "--trace");
+    let max_errors = match args.iter().position(|arg| arg == "--max-errors") {
+        Some(i) => {
+            let value = args.get(i + 1).context("--max-errors expects a number")?;
+            let max_errors = value
+                .parse::<usize>()
+                .with_context(|| format!("--max-errors expects a number, got {}", value))?;
+            args.drain(i..i + 2);
+            Some(max_errors)
+        }
+        None => None,
+    };
+    let options = Options { trace, max_errors, color };
+    match args.first().map(String::as_str) {
+        Some("-") | Some("--stdin") => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            let mut interpreter = interpreter(Interpreter::new().with_warnings(), &options);
+            let result = interpreter.eval_source(&source)?;
+            exit_on_return(&interpreter, &result)?;
+        }
+        Some("-h") | Some("--help") => println!("{}", USAGE),
+        Some("-V") | Some("--version") => println!("lox {}", env!("CARGO_PKG_VERSION")),
+        Some(path) => {
+            let mut interpreter = interpreter(Interpreter::new().with_warnings(), &options);
+            let result = interpreter.eval_file(path)?;
+            exit_on_return(&interpreter, &result)?;
+        }
+        None => repl(interpreter(Interpreter::repl(), &options), &options)?,
+    }
+    Ok(())
+}
+
+struct Options {
+    trace: bool,
+    max_errors: Option<usize>,
+    color: bool,
+}
+
+fn interpreter(
+    mut interpreter: Interpreter,
+    options: &Options,
+) -> Interpreter {
+    if options.trace {
+        interpreter = interpreter.with_trace();
+    }
+    if let Some(max_errors) = options.max_errors {
+        interpreter = interpreter.with_max_errors(max_errors);
+    }
+    interpreter
+}
+
+/// A top-level `return` of an integer becomes the process's exit code. Codes outside 0..=255
+/// would be truncated by the OS, possibly to 0, so they are an error instead.
+fn exit_on_return(
+    interpreter: &Interpreter,
+    result: &EvaluateResult,
+) -> anyhow::Result<()> {
+    if let (true, EvaluateResult::Number(Number::Integer(code))) = (interpreter.returned(), result) {
+        match u8::try_from(*code) {
+            Ok(code) => std::process::exit(code.into()),
+            Err(_) => bail!("exit code must be between 0 and 255, got {}", code),
+        }
+    }
+    Ok(())
+}
+
+/// `Error: ` and the message, with its causes on the same line. With `color` the label is bold
+/// red and the message bold.
+fn render_error(
+    err: &anyhow::Error,
+    color: bool,
+) -> String {
+    if color {
+        format!("\x1b[1;31mError:\x1b[0m \x1b[1m{:#}\x1b[0m", err)
+    } else {
+        format!("Error: {:#}", err)
+    }
+}
+
+fn repl(
+    mut interpreter: Interpreter,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let mut line = String::new();
+    loop {
+        print!("{}", interpreter.prompt());
+        std::io::stdout().flush()?;
+        line.clear();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match interpreter.feed_line(&line) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", render_error(&e, options.color)),
+        }
+    }
+}
@@ -1,148 +1,258 @@
-use std::collections::HashMap;
+use std::fmt::Display;
 
 use anyhow::bail;
 
 use crate::{
-    ast::AstNode,
+    ast::{AstNode, MatchArm},
     token::{KeyWord, TokenType},
 };
 
-// program        → declaration* EOF ;
+static EOF: TokenType = TokenType::Eof;
 
-// declaration    → varDeclaration | statement ;
+// program        → ( importDecl | declaration )* EOF ;
 
-// varDeclaration -> "var" IDENTIFIER ( "=" expression )? ";" ;
+// importDecl     -> "import" STRING ";" ;
 
-// statement      -> exprStmt | printStmt | block ;
+// declaration    → varDeclaration | constDeclaration | globalDeclaration | statement ;
+
+// varDeclaration -> "var" IDENTIFIER ( "=" expression )? ";"
+//                 | "var" "[" IDENTIFIER ( "," IDENTIFIER )* "]" "=" expression ";" ;
+// constDeclaration -> "const" IDENTIFIER "=" expression ";" ;
+// globalDeclaration -> "global" IDENTIFIER "=" expression ";" ;
+
+// statement      -> exprStmt | printStmt | returnStmt | breakStmt | continueStmt | block | switchStmt | ifStmt
+//                 | labeledStmt | forInStmt | whileStmt | repeatStmt ;
 
 // exprStmt       → expression ";" ;
 // printStmt      → "print" expression ";" ;
-// block          -> "{" declaration* "}" ;
+// returnStmt     -> "return" expression? ";" ;
+// breakStmt      -> "break" IDENTIFIER? ";" ;
+// continueStmt   -> "continue" IDENTIFIER? ";" ;
+// switchStmt     -> "switch" expression "{" ( "case" expression ":" declaration )* ( "default" ":" declaration )? "}" ;
+// ifStmt         -> ( "if" | "unless" ) expression "then"? statement ( "else" statement )? ;
+// labeledStmt    -> IDENTIFIER ":" ( forInStmt | whileStmt | repeatStmt ) ;
+// forInStmt      -> "for" IDENTIFIER "in" expression statement ;
+// whileStmt      -> ( "while" | "until" ) expression statement ;
+// repeatStmt     -> "repeat" expression statement ;
+// block          -> "{" ( declaration | deferStmt )* "}" ;
+// deferStmt      -> "defer" statement ;
 
 // expression     → assignment ;
-// assignment     -> IDENTIFIER "=" assignment | equality ;
+// assignment     -> IDENTIFIER "=" assignment | coalesce ;
+// coalesce       -> logicOr ( "??" logicOr )* ;
+// logicOr        -> logicAnd ( "or" logicAnd )* ;
+// logicAnd       -> equality ( "and" equality )* ;
 // equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-// comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+// comparison     → range ( ( ">" | ">=" | "<" | "<=" ) range )* ;
+// range          -> term ( ".." term )? ;
 // term           → factor ( ( "-" | "+" ) factor )* ;
-// factor         → unary ( ( "/" | "*" ) unary )* ;
-// unary          → ( "!" | "-" ) unary | primary ;
-// primary        → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | IDENTIFIER ;
+// factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
+// unary          → ( "!" | "-" | "+" ) unary | power ;
+// power          -> call ( "**" unary )? ;
+// call           -> primary ( "(" arguments? ")" | "[" expression "]" )* ;
+// arguments      -> expression ( "," expression )* ;
+// primary        → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | IDENTIFIER | list | ifExpr | matchExpr ;
+//                | "(" expression ( "," expression )+ ")" ;   (only with sequence expressions enabled)
+// list           -> "[" ( expression ( "," expression )* )? "]" ;
+// ifExpr         -> "if" expression blockExpr "else" ( blockExpr | ifExpr ) ;
+// matchExpr      -> "match" expression "{" matchArm ( "," matchArm )* ","? "}" ;
+// matchArm       -> ( "_" | TYPE IDENTIFIER? ) "=>" expression ;
+// blockExpr      -> "{" declaration* expression? "}" ;
+
+/// A problem in the source that doesn't stop parsing, such as a statement whose value is
+/// thrown away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "warning: {}", self.message)
+    }
+}
+
+/// How many syntax errors `parse` reports before giving up on the rest of the source.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
 
 pub struct Parser {
     tokens: Vec<TokenType>,
     current: usize,
-    scope: Scope,
-}
-
-#[derive(Clone)]
-pub struct Scope {
-    parent: Option<Box<Scope>>,
-    vars: HashMap<String, AstNode>,
+    sequence_expressions: bool,
+    max_errors: usize,
+    warnings: Vec<Warning>,
 }
 
-impl Scope {
-    pub fn get_var(
-        &self,
-        name: &str,
-    ) -> Option<&AstNode> {
-        let local = self.vars.get(name);
-        match local {
-            Some(var) => Some(var),
-            None => match &self.parent {
-                Some(parent) => parent.get_var(name),
-                None => None,
-            },
+impl Parser {
+    pub fn new(tokens: Vec<TokenType>) -> Self {
+        Self {
+            tokens,
+            current: 0,
+            sequence_expressions: false,
+            max_errors: DEFAULT_MAX_ERRORS,
+            warnings: Vec::new(),
         }
     }
 
-    pub fn add_var(
-        &mut self,
-        var: AstNode,
-    ) -> anyhow::Result<()> {
-        if let AstNode::Variable { name, value } = var {
-            self.vars.insert(name.clone(), AstNode::Variable { name, value });
-            return Ok(());
-        }
-        bail!("var: {} is not a AstNode::Variable", var)
+    /// Accepts C's comma operator inside parentheses: `(a, b)` evaluates both and yields `b`.
+    /// Only a parenthesized expression in primary position is a sequence; the parentheses of a
+    /// call belong to the `call` rule, so `f(a, b)` still passes two arguments and `f((a, b))`
+    /// passes one.
+    pub fn with_sequence_expressions(mut self) -> Self {
+        self.sequence_expressions = true;
+        self
     }
 
-    pub fn expire(self) -> Self {
-        match self.parent {
-            Some(parent) => *parent,
-            None => self,
-        }
+    /// Stops collecting syntax errors after `max_errors` of them, so a badly broken file doesn't
+    /// bury the first mistake under hundreds of cascading ones.
+    pub fn with_max_errors(
+        mut self,
+        max_errors: usize,
+    ) -> Self {
+        self.max_errors = max_errors.max(1);
+        self
     }
 
-    pub fn forward(self) -> Self {
-        let parent = Some(Box::new(self));
-        let vars = HashMap::new();
-        Self { parent, vars }
+    /// On a syntax error, skips to the start of the next statement and keeps going, then fails
+    /// with every error found, one per line.
+    pub fn parse(&mut self) -> anyhow::Result<Vec<AstNode>> {
+        self.warnings.clear();
+        self.program()
     }
-}
 
-impl Parser {
-    fn get_var(
-        &self,
-        name: &str,
-    ) -> Option<&AstNode> {
-        self.scope.get_var(name)
+    /// Parses the next top-level declaration, or gives `None` once the tokens run out, so a driver
+    /// can evaluate each statement before the rest is parsed. After an error the parser has
+    /// already skipped to the next statement, so calling again carries on from there.
+    pub fn parse_next(&mut self) -> anyhow::Result<Option<AstNode>> {
+        if self.at_end() {
+            return Ok(None);
+        }
+        let start = self.current;
+        match self.top_level() {
+            Ok(node) => Ok(Some(node)),
+            Err(err) => {
+                self.synchronize(start);
+                Err(err)
+            }
+        }
     }
 
-    fn add_var(
-        &mut self,
-        var: AstNode,
-    ) -> anyhow::Result<()> {
-        self.scope.add_var(var)
+    /// Warnings collected by the last `parse` and any `parse_next` calls since, in source order.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
     }
 
-    pub fn forward_scope(&mut self) {
-        self.scope = self.scope.clone().forward();
+    fn warn(
+        &mut self,
+        message: &str,
+    ) {
+        self.warnings.push(Warning {
+            message: message.to_string(),
+        });
     }
 
-    pub fn expire_scope(&mut self) {
-        self.scope = self.scope.clone().expire();
+    fn program(&mut self) -> anyhow::Result<Vec<AstNode>> {
+        let mut vec = Vec::new();
+        let mut errors = Vec::new();
+        while !self.at_end() {
+            let start = self.current;
+            match self.top_level() {
+                Ok(node) => vec.push(node),
+                Err(err) => {
+                    errors.push(err.to_string());
+                    if errors.len() == self.max_errors {
+                        errors.push("too many errors; stopping".to_string());
+                        break;
+                    }
+                    self.synchronize(start);
+                }
+            }
+        }
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"))
+        }
+        Ok(vec)
     }
-}
 
-impl Parser {
-    pub fn new(tokens: Vec<TokenType>) -> Self {
-        Self {
-            tokens,
-            current: 0,
-            scope: Scope {
-                parent: None,
-                vars: HashMap::new(),
-            },
+    fn top_level(&mut self) -> anyhow::Result<AstNode> {
+        if self.peek().is_keyword(KeyWord::Import) {
+            self.import_declaration()
+        } else {
+            self.declaration()
         }
     }
 
-    pub fn parse(&mut self) -> anyhow::Result<Vec<AstNode>> {
-        self.program()
+    /// Skips past the statement that failed to parse: up to and including the next ';', or up to
+    /// the next keyword that starts a statement, whichever comes first.
+    fn synchronize(
+        &mut self,
+        start: usize,
+    ) {
+        if self.current == start {
+            self.advance();
+        }
+        while !self.at_end() {
+            if self.tokens[self.current - 1] == TokenType::Semicolon {
+                return;
+            }
+            match self.peek().as_keyword() {
+                Some(
+                    KeyWord::Var
+                    | KeyWord::Const
+                    | KeyWord::Global
+                    | KeyWord::Import
+                    | KeyWord::Print
+                    | KeyWord::Return
+                    | KeyWord::Break
+                    | KeyWord::Continue
+                    | KeyWord::If
+                    | KeyWord::Unless
+                    | KeyWord::While
+                    | KeyWord::Until
+                    | KeyWord::For
+                    | KeyWord::Repeat
+                    | KeyWord::Switch,
+                ) => return,
+                _ => self.advance(),
+            }
+        }
     }
 
-    fn program(&mut self) -> anyhow::Result<Vec<AstNode>> {
-        let mut vec = Vec::new();
-        while self.current < self.tokens.len() - 1 {
-            let node = self.declaration()?;
-            vec.push(node);
+    /// Only allowed at the top level, so the imported definitions always land in the global scope.
+    fn import_declaration(&mut self) -> anyhow::Result<AstNode> {
+        // importDecl     -> "import" STRING ";" ;
+        self.forward()?;
+        let TokenType::String(path) = self.peek().clone() else {
+            bail!("Expected a path string after import")
+        };
+        self.forward()?;
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after import")
         }
-        Ok(vec)
+        self.advance();
+        Ok(AstNode::Import(path))
     }
 
     fn declaration(&mut self) -> anyhow::Result<AstNode> {
-        // declaration    → varDeclaration | statement ;
-        let token = self.peek();
-        match token {
-            TokenType::KeyWord(KeyWord::Var) => self.var_declaration(),
+        // declaration    → varDeclaration | constDeclaration | globalDeclaration | statement ;
+        match self.peek().as_keyword() {
+            Some(KeyWord::Var) => self.var_declaration(),
+            Some(KeyWord::Const) => self.const_declaration(),
+            Some(KeyWord::Global) => self.global_declaration(),
             _ => self.statement(),
         }
     }
 
     fn var_declaration(&mut self) -> anyhow::Result<AstNode> {
-        // varDeclaration -> "var" IDENTIFIER ( "=" expression )? ";" ;
+        // varDeclaration -> "var" IDENTIFIER ( "=" expression )? ";"
+        //                 | "var" "[" IDENTIFIER ( "," IDENTIFIER )* "]" "=" expression ";" ;
         self.forward()?;
         let token = self.peek().clone();
         let node = match token {
+            TokenType::LeftBracket => self.destructure()?,
             TokenType::Identifier(var_name) => {
                 self.forward()?;
                 if self.peek() == &TokenType::Equal {
@@ -151,27 +261,20 @@ impl Parser {
                     if self.peek() != &TokenType::Semicolon {
                         bail!("Expected ';' after expression in var declaration")
                     }
-                    if let Err(_e) = self.forward() {
-                        println!("reach the end of the tokens, last token is {}", self.peek())
-                    }
-                    let var = AstNode::Variable {
+                    self.advance();
+                    AstNode::Variable {
                         name: var_name.clone(),
                         value: Some(Box::new(value)),
-                    };
-                    self.add_var(var.clone())?;
-                    var
+                    }
                 } else {
                     let var = AstNode::Variable {
                         name: var_name.clone(),
                         value: None,
                     };
-                    self.add_var(var.clone())?;
                     if self.peek() != &TokenType::Semicolon {
                         bail!("Expected ';' after var declaration")
                     }
-                    if let Err(_e) = self.forward() {
-                        println!("reach the end of the tokens, last token is {}", self.peek())
-                    }
+                    self.advance();
                     var
                 }
             }
@@ -183,14 +286,117 @@ impl Parser {
         Ok(node)
     }
 
+    fn destructure(&mut self) -> anyhow::Result<AstNode> {
+        self.forward()?;
+        let mut names = Vec::new();
+        loop {
+            let TokenType::Identifier(name) = self.peek().clone() else {
+                bail!("Expected identifier in var pattern")
+            };
+            names.push(name);
+            self.forward()?;
+            match self.peek() {
+                TokenType::Comma => self.forward()?,
+                TokenType::RightBracket => break,
+                _ => bail!("Expected ',' or ']' in var pattern"),
+            }
+        }
+        self.forward()?;
+        if self.peek() != &TokenType::Equal {
+            bail!("Expected '=' after var pattern")
+        }
+        self.forward()?;
+        let value = self.expression()?;
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after expression in var declaration")
+        }
+        self.advance();
+        Ok(AstNode::Destructure {
+            names,
+            value: Box::new(value),
+        })
+    }
+
+    fn const_declaration(&mut self) -> anyhow::Result<AstNode> {
+        // constDeclaration -> "const" IDENTIFIER "=" expression ";" ;
+        self.forward()?;
+        let TokenType::Identifier(name) = self.peek().clone() else {
+            bail!("Expected identifier after const")
+        };
+        self.forward()?;
+        if self.peek() != &TokenType::Equal {
+            bail!("Expected '=' after const {}", name)
+        }
+        self.forward()?;
+        let value = self.expression()?;
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after expression in const declaration")
+        }
+        self.advance();
+        Ok(AstNode::Const {
+            name,
+            value: Box::new(value),
+        })
+    }
+
+    /// Binds `name` in the global scope even from inside a block, where `var` would declare a
+    /// local; an existing global is assigned, a missing one defined.
+    fn global_declaration(&mut self) -> anyhow::Result<AstNode> {
+        // globalDeclaration -> "global" IDENTIFIER "=" expression ";" ;
+        self.forward()?;
+        let TokenType::Identifier(name) = self.peek().clone() else {
+            bail!("Expected identifier after global")
+        };
+        self.forward()?;
+        if self.peek() != &TokenType::Equal {
+            bail!("Expected '=' after global {}", name)
+        }
+        self.forward()?;
+        let value = self.expression()?;
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after expression in global declaration")
+        }
+        self.advance();
+        Ok(AstNode::Global {
+            name,
+            value: Box::new(value),
+        })
+    }
+
     fn statement(&mut self) -> anyhow::Result<AstNode> {
-        // statement      -> exprStmt | printStmt | block;
-        let token = self.peek();
-        match token {
-            TokenType::KeyWord(KeyWord::Print) => self.print_statement(),
-            TokenType::LeftBrace => self.block(),
-            _ => self.expression(),
+        // statement      -> exprStmt | printStmt | returnStmt | breakStmt | continueStmt | block | switchStmt | ifStmt
+        //                 | labeledStmt | forInStmt | whileStmt | repeatStmt ;
+        if self.peek() == &TokenType::LeftBrace {
+            return self.block();
+        }
+        if self.at_label() {
+            return self.labeled_statement();
+        }
+        match self.peek().as_keyword() {
+            Some(KeyWord::Print) => self.print_statement(),
+            Some(KeyWord::Return) => self.return_statement(),
+            Some(KeyWord::Break | KeyWord::Continue) => self.jump_statement(),
+            Some(KeyWord::Switch) => self.switch_statement(),
+            Some(KeyWord::If | KeyWord::Unless) => self.if_statement(),
+            Some(KeyWord::For) if self.peek_n(2).is_some_and(|token| token.is_keyword(KeyWord::In)) => self.for_in_statement(),
+            Some(KeyWord::For) => bail!("Expected 'for IDENTIFIER in expression'"),
+            Some(KeyWord::While | KeyWord::Until) => self.while_statement(),
+            Some(KeyWord::Repeat) => self.repeat_statement(),
+            _ => self.expr_statement(),
+        }
+    }
+
+    fn expr_statement(&mut self) -> anyhow::Result<AstNode> {
+        // exprStmt       → expression ";" ;
+        let expr = self.expression()?;
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after expression")
+        }
+        self.advance();
+        if !has_effect(&expr) {
+            self.warn("statement has no effect");
         }
+        Ok(AstNode::ExprStmt(Box::new(expr)))
     }
 
     fn print_statement(&mut self) -> anyhow::Result<AstNode> {
@@ -199,72 +405,292 @@ impl Parser {
         if self.peek() != &TokenType::Semicolon {
             bail!("Expected ';' after expression in print statement")
         }
-        if let Err(_e) = self.forward() {
-            println!("reach the end of the tokens, last token is {}", self.peek())
-        }
+        self.advance();
         Ok(AstNode::Print(Box::new(expr)))
     }
 
+    /// There are no functions, so `return` ends the whole program (or the imported file it is in).
+    fn return_statement(&mut self) -> anyhow::Result<AstNode> {
+        // returnStmt     -> "return" expression? ";" ;
+        self.forward()?;
+        let value = match self.peek() {
+            TokenType::Semicolon => None,
+            _ => Some(Box::new(self.expression()?)),
+        };
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after return value")
+        }
+        self.advance();
+        Ok(AstNode::Return(value))
+    }
+
+    /// `break` leaves a loop and `continue` starts its next iteration. Without a label they apply
+    /// to the innermost loop, with one to the enclosing loop of that label.
+    fn jump_statement(&mut self) -> anyhow::Result<AstNode> {
+        // breakStmt      -> "break" IDENTIFIER? ";" ;
+        // continueStmt   -> "continue" IDENTIFIER? ";" ;
+        let is_break = self.peek().is_keyword(KeyWord::Break);
+        self.forward()?;
+        let label = match self.peek() {
+            TokenType::Identifier(label) => {
+                let label = label.clone();
+                self.forward()?;
+                Some(label)
+            }
+            _ => None,
+        };
+        if self.peek() != &TokenType::Semicolon {
+            bail!("Expected ';' after '{}'", if is_break { "break" } else { "continue" })
+        }
+        self.advance();
+        Ok(if is_break { AstNode::Break(label) } else { AstNode::Continue(label) })
+    }
+
+    /// Whether the next tokens are a loop label, `IDENTIFIER ":"`.
+    fn at_label(&self) -> bool {
+        matches!(self.peek(), TokenType::Identifier(_)) && self.peek_n(1) == Some(&TokenType::Colon)
+    }
+
+    fn labeled_statement(&mut self) -> anyhow::Result<AstNode> {
+        // labeledStmt    -> IDENTIFIER ":" ( forInStmt | whileStmt | repeatStmt ) ;
+        let TokenType::Identifier(label) = self.peek().clone() else {
+            bail!("Expected a label")
+        };
+        self.forward()?;
+        self.forward()?;
+        if !matches!(
+            self.peek().as_keyword(),
+            Some(KeyWord::For | KeyWord::While | KeyWord::Until | KeyWord::Repeat)
+        ) {
+            bail!("Expected a loop after label '{}'", label)
+        }
+        Ok(AstNode::Labeled {
+            label,
+            body: Box::new(self.statement()?),
+        })
+    }
+
     fn block(&mut self) -> anyhow::Result<AstNode> {
-        // block          -> "{" declaration* "}" ;
+        // block          -> "{" ( declaration | deferStmt )* "}" ;
         self.forward()?;
-        self.forward_scope();
         let mut vec = Vec::new();
         while self.peek() != &TokenType::RightBrace {
-            let node = self.declaration()?;
+            let node = if self.peek().is_keyword(KeyWord::Defer) {
+                self.defer_statement()?
+            } else {
+                self.declaration()?
+            };
             vec.push(node);
         }
-        self.expire_scope();
         if self.peek() != &TokenType::RightBrace {
             bail!("Expected '}}' after block")
         }
-        if let Err(_e) = self.forward() {
-            println!("reach the end of the tokens, last token is {}", self.peek())
+        self.advance();
+        if vec.is_empty() {
+            self.warn("empty block");
         }
         Ok(AstNode::Block(vec))
     }
 
+    /// Only allowed directly inside a block, whose exit runs the deferred statement.
+    fn defer_statement(&mut self) -> anyhow::Result<AstNode> {
+        // deferStmt      -> "defer" statement ;
+        self.forward()?;
+        let statement = self.statement()?;
+        Ok(AstNode::Defer(Box::new(statement)))
+    }
+
+    fn switch_statement(&mut self) -> anyhow::Result<AstNode> {
+        // switchStmt     -> "switch" expression "{" ( "case" expression ":" declaration )* ( "default" ":" declaration )? "}" ;
+        self.forward()?;
+        let scrutinee = self.expression()?;
+        if self.peek() != &TokenType::LeftBrace {
+            bail!("Expected '{{' after switch expression")
+        }
+        self.forward()?;
+        let mut cases = Vec::new();
+        let mut default = None;
+        while self.peek() != &TokenType::RightBrace {
+            match self.peek().as_keyword() {
+                Some(KeyWord::Case) if default.is_none() => {
+                    self.forward()?;
+                    let value = self.expression()?;
+                    if self.peek() != &TokenType::Colon {
+                        bail!("Expected ':' after case value")
+                    }
+                    self.forward()?;
+                    let body = self.declaration()?;
+                    cases.push((value, body));
+                }
+                Some(KeyWord::Default) if default.is_none() => {
+                    self.forward()?;
+                    if self.peek() != &TokenType::Colon {
+                        bail!("Expected ':' after default")
+                    }
+                    self.forward()?;
+                    default = Some(Box::new(self.declaration()?));
+                }
+                _ => bail!("Expected 'case' or a single trailing 'default' in switch"),
+            }
+        }
+        self.advance();
+        Ok(AstNode::Switch {
+            scrutinee: Box::new(scrutinee),
+            cases,
+            default,
+        })
+    }
+
+    /// `unless c` is sugar for `if !c`.
+    fn if_statement(&mut self) -> anyhow::Result<AstNode> {
+        // ifStmt         -> ( "if" | "unless" ) expression "then"? statement ( "else" statement )? ;
+        let negate = self.peek().is_keyword(KeyWord::Unless);
+        self.forward()?;
+        let condition = self.condition(negate)?;
+        // `then` only reads better; `if x then print 1;` is `if x print 1;`.
+        if self.peek().is_keyword(KeyWord::Then) {
+            self.forward()?;
+        }
+        let then_branch = self.statement()?;
+        let else_branch = if self.peek().is_keyword(KeyWord::Else) {
+            self.forward()?;
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+        Ok(AstNode::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+        })
+    }
+
+    fn for_in_statement(&mut self) -> anyhow::Result<AstNode> {
+        // forInStmt      -> "for" IDENTIFIER "in" expression statement ;
+        self.forward()?;
+        let name = match self.peek() {
+            TokenType::Identifier(name) => name.clone(),
+            _ => bail!("Expected loop variable after 'for'"),
+        };
+        self.forward()?;
+        if !self.peek().is_keyword(KeyWord::In) {
+            bail!("Expected 'in' after loop variable")
+        }
+        self.forward()?;
+        let iterable = self.expression()?;
+        let body = self.statement()?;
+        Ok(AstNode::ForIn {
+            name,
+            iterable: Box::new(iterable),
+            body: Box::new(body),
+        })
+    }
+
+    /// `until c` is sugar for `while !c`.
+    fn while_statement(&mut self) -> anyhow::Result<AstNode> {
+        // whileStmt      -> ( "while" | "until" ) expression statement ;
+        let negate = self.peek().is_keyword(KeyWord::Until);
+        self.forward()?;
+        let condition = self.condition(negate)?;
+        let body = self.statement()?;
+        Ok(AstNode::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+        })
+    }
+
+    fn condition(
+        &mut self,
+        negate: bool,
+    ) -> anyhow::Result<AstNode> {
+        let condition = self.expression()?;
+        if !negate {
+            return Ok(condition);
+        }
+        Ok(AstNode::Unary {
+            operator: '!',
+            operand: Box::new(condition),
+        })
+    }
+
+    fn repeat_statement(&mut self) -> anyhow::Result<AstNode> {
+        // repeatStmt     -> "repeat" expression statement ;
+        self.forward()?;
+        let count = self.expression()?;
+        let body = self.statement()?;
+        Ok(AstNode::Repeat {
+            count: Box::new(count),
+            body: Box::new(body),
+        })
+    }
+
     fn expression(&mut self) -> anyhow::Result<AstNode> {
         // expression     → assignment ;
         self.assignment()
     }
 
     fn assignment(&mut self) -> anyhow::Result<AstNode> {
-        // assignment     -> IDENTIFIER "=" assignment | equality ;
-        let token = self.peek().clone();
-        match token {
-            TokenType::Identifier(var_name) => {
-                let v = self.get_var(&var_name);
-                if v.is_some() {
-                    if self.forward().is_err() {
-                        bail!("Unfinished assignment")
-                    }
-                    if self.peek() == &TokenType::Equal {
-                        self.forward()?;
-                        let value = self.assignment()?;
-                        let var = AstNode::Variable {
-                            name: var_name.clone(),
-                            value: Some(Box::new(value)),
-                        };
-                        self.add_var(var.clone())?;
-                        if self.peek() != &TokenType::Semicolon {
-                            bail!("Expected ';' after assignment")
-                        }
-                        if self.next().is_some() {
-                            self.forward()?;
-                        }
-
-                        Ok(var)
-                    } else {
-                        let var = self.get_var(&var_name).unwrap().clone();
-                        Ok(var)
-                    }
-                } else {
-                    bail!("Variable {} not declared", var_name)
-                }
+        // assignment     -> IDENTIFIER "=" assignment | coalesce ;
+        let node = self.coalesce()?;
+        if self.peek() != &TokenType::Equal {
+            return Ok(node);
+        }
+        match node {
+            AstNode::Identifier { name, .. } => {
+                self.forward()?;
+                let value = self.assignment()?;
+                Ok(AstNode::Assign {
+                    name,
+                    value: Box::new(value),
+                    depth: None,
+                })
             }
-            _ => self.equality(),
+            _ => bail!("Invalid assignment target {}", node),
+        }
+    }
+
+    fn coalesce(&mut self) -> anyhow::Result<AstNode> {
+        // coalesce       -> logicOr ( "??" logicOr )* ;
+        let mut node = self.logic_or()?;
+        while self.peek() == &TokenType::QuestionQuestion {
+            self.forward()?;
+            let right = self.logic_or()?;
+            node = AstNode::NilCoalesce {
+                left: Box::new(node),
+                right: Box::new(right),
+            };
         }
+        Ok(node)
+    }
+
+    fn logic_or(&mut self) -> anyhow::Result<AstNode> {
+        // logicOr        -> logicAnd ( "or" logicAnd )* ;
+        let mut node = self.logic_and()?;
+        while self.peek().is_keyword(KeyWord::Or) {
+            self.forward()?;
+            let right = self.logic_and()?;
+            node = AstNode::Binary {
+                left: Box::new(node),
+                operator: "or".to_string(),
+                right: Box::new(right),
+            };
+        }
+        Ok(node)
+    }
+
+    fn logic_and(&mut self) -> anyhow::Result<AstNode> {
+        // logicAnd       -> equality ( "and" equality )* ;
+        let mut node = self.equality()?;
+        while self.peek().is_keyword(KeyWord::And) {
+            self.forward()?;
+            let right = self.equality()?;
+            node = AstNode::Binary {
+                left: Box::new(node),
+                operator: "and".to_string(),
+                right: Box::new(right),
+            };
+        }
+        Ok(node)
     }
 
     fn equality(&mut self) -> anyhow::Result<AstNode> {
@@ -291,16 +717,16 @@ impl Parser {
     }
 
     fn comparison(&mut self) -> anyhow::Result<AstNode> {
-        // comparison -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+        // comparison -> range ( ( ">" | ">=" | "<" | "<=" ) range )* ;
 
-        let mut node = self.term()?;
+        let mut node = self.range()?;
 
         loop {
             let token = self.peek();
             if token == &TokenType::Greater || token == &TokenType::GreaterEqual || token == &TokenType::Less || token == &TokenType::LessEqual {
                 let operator = token.to_string();
                 self.forward()?;
-                let right = self.term()?;
+                let right = self.range()?;
                 node = AstNode::Binary {
                     left: Box::new(node),
                     operator,
@@ -314,6 +740,21 @@ impl Parser {
         Ok(node)
     }
 
+    fn range(&mut self) -> anyhow::Result<AstNode> {
+        // range -> term ( ".." term )? ;
+        let node = self.term()?;
+        if self.peek() != &TokenType::DotDot {
+            return Ok(node);
+        }
+        self.forward()?;
+        let end = self.term()?;
+        Ok(AstNode::Binary {
+            left: Box::new(node),
+            operator: "..".to_string(),
+            right: Box::new(end),
+        })
+    }
+
     fn term(&mut self) -> anyhow::Result<AstNode> {
         // term -> factor ( ( "-" | "+" ) factor )* ;
         let mut node = self.factor()?;
@@ -337,14 +778,14 @@ impl Parser {
     }
 
     fn factor(&mut self) -> anyhow::Result<AstNode> {
-        // factor -> unary ( ( "/" | "*" ) unary )* ;
+        // factor -> unary ( ( "/" | "*" | "%" ) unary )* ;
 
         let mut left = self.unary()?;
 
         loop {
             let token = self.peek().clone();
 
-            if token == TokenType::Slash || token == TokenType::Star {
+            if token == TokenType::Slash || token == TokenType::Star || token == TokenType::Percent {
                 let operator = token.to_string();
                 self.forward()?;
                 let right = self.unary()?;
@@ -362,9 +803,9 @@ impl Parser {
     }
 
     fn unary(&mut self) -> anyhow::Result<AstNode> {
-        // unary -> ( "!" | "-" ) unary | primary ;
+        // unary -> ( "!" | "-" | "+" ) unary | power ;
         let token = self.peek();
-        if token == &TokenType::Bang || token == &TokenType::Minus {
+        if token == &TokenType::Bang || token == &TokenType::Minus || token == &TokenType::Plus {
             let operator = token.to_string();
             self.forward()?;
             let operand = self.unary()?;
@@ -374,7 +815,63 @@ impl Parser {
             });
         }
 
-        self.primary()
+        self.power()
+    }
+
+    fn power(&mut self) -> anyhow::Result<AstNode> {
+        // power -> call ( "**" unary )? ;
+        // Right associative, and binds tighter than a unary operator on its left: -2 ** 2 is -4.
+        let node = self.call()?;
+        if self.peek() != &TokenType::StarStar {
+            return Ok(node);
+        }
+        self.forward()?;
+        let exponent = self.unary()?;
+        Ok(AstNode::Binary {
+            left: Box::new(node),
+            operator: "**".to_string(),
+            right: Box::new(exponent),
+        })
+    }
+
+    fn call(&mut self) -> anyhow::Result<AstNode> {
+        // call -> primary ( "(" arguments? ")" | "[" expression "]" )* ;
+        let mut node = self.primary()?;
+        while matches!(self.peek(), TokenType::LeftParen | TokenType::LeftBracket) {
+            if self.peek() == &TokenType::LeftBracket {
+                self.forward()?;
+                let index = self.expression()?;
+                if self.peek() != &TokenType::RightBracket {
+                    bail!("Expected ']' after index")
+                }
+                self.advance();
+                node = AstNode::Index {
+                    object: Box::new(node),
+                    index: Box::new(index),
+                };
+                continue;
+            }
+            self.forward()?;
+            let mut arguments = Vec::new();
+            if self.peek() != &TokenType::RightParen {
+                loop {
+                    arguments.push(self.expression()?);
+                    if self.peek() != &TokenType::Comma {
+                        break;
+                    }
+                    self.forward()?;
+                }
+            }
+            if self.peek() != &TokenType::RightParen {
+                bail!("Expected ')' after arguments")
+            }
+            self.advance();
+            node = AstNode::Call {
+                callee: Box::new(node),
+                arguments,
+            };
+        }
+        Ok(node)
     }
 
     fn primary(&mut self) -> anyhow::Result<AstNode> {
@@ -388,6 +885,8 @@ impl Parser {
                 KeyWord::True => AstNode::Boolean(true),
                 KeyWord::False => AstNode::Boolean(false),
                 KeyWord::Nil => AstNode::Nil,
+                KeyWord::If => return self.if_expression(),
+                KeyWord::Match => return self.match_expression(),
                 _ => {
                     bail!("Unexpected keyword {:?}", keyword)
                 }
@@ -395,6 +894,16 @@ impl Parser {
             TokenType::LeftParen => {
                 self.forward()?;
                 let expr = self.expression()?;
+                let expr = if self.sequence_expressions && self.peek() == &TokenType::Comma {
+                    let mut nodes = vec![expr];
+                    while self.peek() == &TokenType::Comma {
+                        self.forward()?;
+                        nodes.push(self.expression()?);
+                    }
+                    AstNode::Sequence(nodes)
+                } else {
+                    expr
+                };
                 if self.peek() != &TokenType::RightParen {
                     bail!("Expected ')' after expression")
                 }
@@ -403,50 +912,220 @@ impl Parser {
             TokenType::RightParen => {
                 bail!("Unexpected ')' in parsing primary")
             }
-            TokenType::Identifier(var_name) => {
-                if let Some(var) = self.get_var(&var_name) {
-                    var.clone()
-                } else {
-                    bail!("Variable {} not declared", var_name)
+            TokenType::LeftBracket => {
+                // list -> "[" ( expression ( "," expression )* )? "]" ;
+                self.forward()?;
+                let mut elements = Vec::new();
+                if self.peek() != &TokenType::RightBracket {
+                    loop {
+                        elements.push(self.expression()?);
+                        if self.peek() != &TokenType::Comma {
+                            break;
+                        }
+                        self.forward()?;
+                    }
+                }
+                if self.peek() != &TokenType::RightBracket {
+                    bail!("Expected ']' after list elements")
                 }
+                AstNode::List(elements)
             }
+            TokenType::Identifier(name) => AstNode::Identifier { name, depth: None },
             _ => {
                 bail!("Expected expression in parsing primary")
             }
         };
 
-        match self.forward() {
-            Ok(_) => {}
-            Err(_) => {
-                println!("last token")
+        self.advance();
+        Ok(node)
+    }
+
+    /// `if` used as a value, as in `var x = if cond { 1 } else { 2 };`. Unlike the statement,
+    /// both branches are required and each must be a block.
+    fn if_expression(&mut self) -> anyhow::Result<AstNode> {
+        // ifExpr         -> "if" expression blockExpr "else" ( blockExpr | ifExpr ) ;
+        self.forward()?;
+        let condition = self.expression()?;
+        let then_branch = self.block_expression()?;
+        if !self.peek().is_keyword(KeyWord::Else) {
+            bail!("Expected 'else' in if expression")
+        }
+        self.forward()?;
+        let else_branch = if self.peek().is_keyword(KeyWord::If) {
+            self.if_expression()?
+        } else {
+            self.block_expression()?
+        };
+        Ok(AstNode::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Some(Box::new(else_branch)),
+        })
+    }
+
+    /// Picks an arm by the type of the value, where `TYPE` is one of the names `type_name` gives
+    /// (`number`, `string`, `boolean`, `nil`, `list`, `map` or `function`) and `_` matches anything.
+    fn match_expression(&mut self) -> anyhow::Result<AstNode> {
+        // matchExpr      -> "match" expression "{" matchArm ( "," matchArm )* ","? "}" ;
+        self.forward()?;
+        let scrutinee = self.expression()?;
+        if self.peek() != &TokenType::LeftBrace {
+            bail!("Expected '{{' after match expression")
+        }
+        self.forward()?;
+        let mut arms = Vec::new();
+        while self.peek() != &TokenType::RightBrace {
+            // matchArm       -> ( "_" | TYPE IDENTIFIER? ) "=>" expression ;
+            let pattern = match self.peek() {
+                TokenType::KeyWord(KeyWord::Nil) => "nil".to_string(),
+                TokenType::Identifier(name) if matches!(name.as_str(), "_" | "number" | "string" | "boolean" | "list" | "map" | "function") => {
+                    name.clone()
+                }
+                other => bail!("Expected a type name or '_' in match arm, got '{}'", other),
+            };
+            self.forward()?;
+            let binding = match self.peek() {
+                TokenType::Identifier(name) if pattern != "_" => {
+                    let name = name.clone();
+                    self.forward()?;
+                    Some(name)
+                }
+                _ => None,
+            };
+            if self.peek() != &TokenType::FatArrow {
+                bail!("Expected '=>' after match pattern")
+            }
+            self.forward()?;
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, binding, body });
+            match self.peek() {
+                TokenType::Comma => self.forward()?,
+                TokenType::RightBrace => {}
+                _ => bail!("Expected ',' or '}}' after match arm"),
             }
         }
-        Ok(node)
+        self.advance();
+        Ok(AstNode::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        })
     }
 
+    /// A block whose last entry may be an expression without a `;`. Blocks evaluate to the value of
+    /// their last entry, so that expression is the block's value.
+    fn block_expression(&mut self) -> anyhow::Result<AstNode> {
+        // blockExpr      -> "{" declaration* expression? "}" ;
+        if self.peek() != &TokenType::LeftBrace {
+            bail!("Expected '{{' in if expression")
+        }
+        self.forward()?;
+        let mut vec = Vec::new();
+        while self.peek() != &TokenType::RightBrace {
+            let starts_statement = self.peek() == &TokenType::LeftBrace
+                || self.at_label()
+                || matches!(
+                    self.peek().as_keyword(),
+                    Some(
+                        KeyWord::Var
+                            | KeyWord::Const
+                            | KeyWord::Global
+                            | KeyWord::Print
+                            | KeyWord::Return
+                            | KeyWord::Break
+                            | KeyWord::Continue
+                            | KeyWord::Switch
+                            | KeyWord::If
+                            | KeyWord::Unless
+                            | KeyWord::For
+                            | KeyWord::While
+                            | KeyWord::Until
+                            | KeyWord::Repeat
+                    )
+                );
+            if starts_statement {
+                vec.push(self.declaration()?);
+                continue;
+            }
+            let expr = self.expression()?;
+            if self.peek() != &TokenType::Semicolon {
+                vec.push(expr);
+                break;
+            }
+            self.forward()?;
+            vec.push(AstNode::ExprStmt(Box::new(expr)));
+        }
+        if self.peek() != &TokenType::RightBrace {
+            bail!("Expected '}}' after block")
+        }
+        self.advance();
+        Ok(AstNode::Block(vec))
+    }
+
+    /// `TokenType::Eof` once every token has been consumed.
     fn peek(&self) -> &TokenType {
-        &self.tokens[self.current]
+        self.tokens.get(self.current).unwrap_or(&EOF)
+    }
+
+    fn at_end(&self) -> bool {
+        self.current >= self.tokens.len()
     }
 
-    fn next(&self) -> Option<&TokenType> {
-        if self.current == self.tokens.len() - 1 {
-            return None;
+    /// The token `n` positions ahead of the current one, `peek_n(0)` being the current token.
+    fn peek_n(
+        &self,
+        n: usize,
+    ) -> Option<&TokenType> {
+        self.tokens.get(self.current + n)
+    }
+
+    /// Consumes the current token. Unlike `forward`, consuming the last one is fine, e.g. a
+    /// trailing ';', and leaves the parser at the end of input.
+    fn advance(&mut self) {
+        if !self.at_end() {
+            self.current += 1;
         }
-        Some(&self.tokens[self.current + 1])
     }
 
+    /// Moves to the next token, which must exist. Tokens carry no source positions, so running out
+    /// of input is reported with the index and text of the last token instead.
     fn forward(&mut self) -> anyhow::Result<()> {
-        if self.current == self.tokens.len() - 1 {
-            bail!("Already at the end of the tokens");
+        if self.current + 1 >= self.tokens.len() {
+            let last = self.current.min(self.tokens.len().saturating_sub(1));
+            match self.tokens.get(last) {
+                Some(token) => bail!("Unexpected end of input after '{}' (token {})", token, last),
+                None => bail!("Unexpected end of input"),
+            }
         }
         self.current += 1;
         Ok(())
     }
 }
 
+/// Whether evaluating `node` as a statement can do anything observable: only calls and
+/// assignments can, so `1 + 2;` or a lone variable name is dead code.
+fn has_effect(node: &AstNode) -> bool {
+    match node {
+        AstNode::Call { .. } | AstNode::Assign { .. } => true,
+        AstNode::Binary { left, right, .. } | AstNode::NilCoalesce { left, right } => has_effect(left) || has_effect(right),
+        AstNode::Index { object, index } => has_effect(object) || has_effect(index),
+        AstNode::Unary { operand, .. } => has_effect(operand),
+        AstNode::Group(inner) => has_effect(inner),
+        AstNode::List(items) | AstNode::Sequence(items) => items.iter().any(has_effect),
+        AstNode::Boolean(_) | AstNode::Nil | AstNode::Number(_) | AstNode::String(_) | AstNode::Identifier { .. } => false,
+        _ => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{lexing::lexing, parsing::Parser, token::TokenType};
+    use crate::{
+        ast::AstNode,
+        environment::Environment,
+        lexing::{lexing, lexing_str},
+        parsing::Parser,
+        resolving::Resolver,
+        token::{KeyWord, Number, TokenType},
+    };
 
     #[test]
     fn test_parse() {
@@ -472,12 +1151,196 @@ mod tests {
         println!("{:?}", tokens);
 
         let mut parser = Parser::new(tokens);
-        let node = parser.parse().unwrap();
+        let mut node = parser.parse().unwrap();
+        Resolver::new().resolve(&mut node);
         println!("{}", node.len());
+        let mut env = Environment::default();
         for n in node {
             println!("{}", n);
-            let result = n.evaluate();
+            let result = n.evaluate(&mut env).unwrap();
             println!("{:?}", result);
         }
     }
+
+    #[test]
+    fn peek_n() {
+        let tokens = lexing_str("for x in [1] {}").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let parser = Parser::new(tokens.clone());
+        assert_eq!(parser.peek_n(0), Some(&tokens[0]));
+        assert_eq!(parser.peek_n(2), Some(&TokenType::KeyWord(KeyWord::In)));
+        assert_eq!(parser.peek_n(tokens.len()), None);
+
+        // `for` is only recognized as a loop when the token after the variable is `in`.
+        let tokens = lexing_str("for x = 1 {}").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let Err(err) = Parser::new(tokens).parse() else {
+            panic!("expected a parse error")
+        };
+        assert_eq!(err.to_string(), "Expected 'for IDENTIFIER in expression'");
+    }
+
+    #[test]
+    fn warnings() {
+        let tokens = lexing_str("print 1;\n{}\n1 + 2;").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let mut parser = Parser::new(tokens);
+        parser.parse().unwrap();
+        let warnings = parser.warnings().iter().map(|warning| warning.message.as_str()).collect::<Vec<&str>>();
+        assert_eq!(warnings, vec!["empty block", "statement has no effect"]);
+
+        // Every `parse` starts a fresh list; here nothing is left to parse.
+        parser.parse().unwrap();
+        assert!(parser.warnings().is_empty());
+    }
+
+    #[test]
+    fn structural_equality() {
+        let tokens = lexing_str("var x = -1 + 2 * y;").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let ast = Parser::new(tokens).parse().unwrap();
+
+        let expected = AstNode::Variable {
+            name: "x".to_string(),
+            value: Some(Box::new(AstNode::Binary {
+                left: Box::new(AstNode::Unary {
+                    operator: '-',
+                    operand: Box::new(AstNode::Number(Number::Integer(1))),
+                }),
+                operator: "+".to_string(),
+                right: Box::new(AstNode::Binary {
+                    left: Box::new(AstNode::Number(Number::Integer(2))),
+                    operator: "*".to_string(),
+                    right: Box::new(AstNode::Identifier {
+                        name: "y".to_string(),
+                        depth: None,
+                    }),
+                }),
+            })),
+        };
+        assert_eq!(ast, vec![expected]);
+    }
+
+    #[test]
+    fn expr_statement() {
+        let tokens = lexing_str("1 + 2;").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let expected = AstNode::ExprStmt(Box::new(AstNode::Binary {
+            left: Box::new(AstNode::Number(Number::Integer(1))),
+            operator: "+".to_string(),
+            right: Box::new(AstNode::Number(Number::Integer(2))),
+        }));
+        assert_eq!(ast, vec![expected]);
+
+        let tokens = lexing_str("1 + 2 print 3;").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let err = Parser::new(tokens).parse().unwrap_err();
+        assert_eq!(err.to_string(), "Expected ';' after expression");
+
+        // A trailing statement missing its ';' is reported, even when it is a single token.
+        for source in ["1", "x", "print 1; x", "print 1; 1 + 2"] {
+            let tokens = lexing_str(source).unwrap();
+            let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+            let err = Parser::new(tokens).parse().unwrap_err();
+            assert_eq!(err.to_string(), "Expected ';' after expression", "{}", source);
+        }
+    }
+
+    #[test]
+    fn truncated_input() {
+        let tokens = lexing_str("var x = 1 +").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let err = Parser::new(tokens).parse().unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected end of input after '+' (token 4)");
+
+        for source in ["{", "var", "if"] {
+            let tokens = lexing_str(source).unwrap();
+            let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+            let err = Parser::new(tokens).parse().unwrap_err();
+            assert_eq!(err.to_string(), format!("Unexpected end of input after '{}' (token 0)", source));
+        }
+    }
+
+    #[test]
+    fn name_based_nodes() {
+        // The parser keeps no scopes: names are left unresolved for the resolver and runtime, so
+        // reassigning a variable declared earlier needs no parse-time bookkeeping.
+        let tokens = lexing_str("var x = 1;\nx = x + 1;").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let AstNode::ExprStmt(assign) = &ast[1] else {
+            panic!("expected expression statement")
+        };
+        let AstNode::Assign { value, depth: None, .. } = &**assign else {
+            panic!("expected unresolved assignment")
+        };
+        assert!(matches!(**value, AstNode::Binary { ref left, .. } if matches!(**left, AstNode::Identifier { depth: None, .. })));
+
+        let mut env = Environment::default();
+        crate::evaluating::evaluate_program(&ast, &mut env).unwrap();
+        assert_eq!(env.get("x").unwrap(), crate::evaluating::EvaluateResult::Number(Number::Integer(2)));
+    }
+
+    #[test]
+    fn optional_then() {
+        let parse = |source: &str| {
+            let tokens = lexing_str(source).unwrap().into_iter().filter(|token| !token.is_skippable()).collect();
+            Parser::new(tokens).parse().unwrap()
+        };
+        assert_eq!(parse("if x > 0 then print 1;"), parse("if x > 0 print 1;"));
+        assert_eq!(
+            parse("unless x then { print 1; } else if y then print 2; else print 3;"),
+            parse("unless x { print 1; } else if y print 2; else print 3;")
+        );
+    }
+
+    #[test]
+    fn parse_next() {
+        let tokens = |source: &str| {
+            lexing_str(source)
+                .unwrap()
+                .into_iter()
+                .filter(|token| !token.is_skippable())
+                .collect::<Vec<_>>()
+        };
+        let source = "var a = 1;\n{ print a; }\nwhile a < 3 a = a + 1;\nprint a;";
+        let program = Parser::new(tokens(source)).parse().unwrap();
+        let mut parser = Parser::new(tokens(source));
+        let mut streamed = Vec::new();
+        while let Some(node) = parser.parse_next().unwrap() {
+            streamed.push(node);
+        }
+        assert_eq!(streamed, program);
+        assert_eq!(parser.parse_next().unwrap(), None);
+
+        // A bad statement fails on its own and the next call picks up after it.
+        let mut parser = Parser::new(tokens("print 1;\nvar = 2;\nprint 3;"));
+        assert!(parser.parse_next().unwrap().is_some());
+        assert_eq!(parser.parse_next().unwrap_err().to_string(), "Expected identifier after var");
+        assert_eq!(parser.parse_next().unwrap().unwrap().to_string(), "Print 3");
+        assert_eq!(parser.parse_next().unwrap(), None);
+
+        // A last statement starting on the final token is still parsed, not dropped.
+        let mut parser = Parser::new(tokens("print 1; x"));
+        assert!(parser.parse_next().unwrap().is_some());
+        assert_eq!(parser.parse_next().unwrap_err().to_string(), "Expected ';' after expression");
+        assert_eq!(parser.parse_next().unwrap(), None);
+    }
+
+    #[test]
+    fn error_recovery() {
+        let parse = |source: &str, max_errors: usize| {
+            let tokens = lexing_str(source).unwrap().into_iter().filter(|token| !token.is_skippable()).collect();
+            Parser::new(tokens).with_max_errors(max_errors).parse().unwrap_err().to_string()
+        };
+        assert_eq!(
+            parse("var a = ; print 1; print ); var b = 2;", 20),
+            "Expected expression in parsing primary\nUnexpected ')' in parsing primary"
+        );
+        assert_eq!(
+            parse("var a = ; var b = ; var c = ;", 2),
+            "Expected expression in parsing primary\nExpected expression in parsing primary\ntoo many errors; stopping"
+        );
+    }
 }
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+
+use crate::ast::AstNode;
+
+/// Annotates every variable reference with the number of scopes between the reference and the
+/// scope that declares it, so the evaluator can jump straight to the right `Environment`.
+/// References left as `None` are globals.
+#[derive(Default)]
+pub struct Resolver {
+    scopes: Vec<HashSet<String>>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resolve(
+        &mut self,
+        nodes: &mut [AstNode],
+    ) {
+        for node in nodes {
+            self.resolve_node(node);
+        }
+    }
+
+    fn resolve_node(
+        &mut self,
+        node: &mut AstNode,
+    ) {
+        match node {
+            AstNode::Block(nodes) => {
+                self.scopes.push(HashSet::new());
+                self.resolve(nodes);
+                self.scopes.pop();
+            }
+            AstNode::Variable { name, value } => {
+                if let Some(value) = value {
+                    self.resolve_node(value);
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone());
+                }
+            }
+            AstNode::Const { name, value } => {
+                self.resolve_node(value);
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone());
+                }
+            }
+            AstNode::Destructure { names, value } => {
+                self.resolve_node(value);
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.extend(names.iter().cloned());
+                }
+            }
+            // Binds in the global scope whatever the nesting, so it declares nothing locally.
+            AstNode::Global { value, .. } => self.resolve_node(value),
+            AstNode::Identifier { name, depth } => *depth = self.resolve_local(name),
+            AstNode::Assign { name, value, depth } => {
+                self.resolve_node(value);
+                *depth = self.resolve_local(name);
+            }
+            AstNode::Binary { left, right, .. } | AstNode::NilCoalesce { left, right } | AstNode::Index { object: left, index: right } => {
+                self.resolve_node(left);
+                self.resolve_node(right);
+            }
+            AstNode::Unary { operand, .. } | AstNode::Return(Some(operand)) => self.resolve_node(operand),
+            AstNode::Call { callee, arguments } => {
+                self.resolve_node(callee);
+                self.resolve(arguments);
+            }
+            AstNode::List(elements) | AstNode::Sequence(elements) => self.resolve(elements),
+            AstNode::While { condition, body } => {
+                self.resolve_node(condition);
+                self.resolve_node(body);
+            }
+            AstNode::Repeat { count, body } => {
+                self.resolve_node(count);
+                self.scopes.push(HashSet::new());
+                self.resolve_node(body);
+                self.scopes.pop();
+            }
+            AstNode::ForIn { name, iterable, body } => {
+                self.resolve_node(iterable);
+                self.scopes.push(HashSet::from([name.clone()]));
+                self.resolve_node(body);
+                self.scopes.pop();
+            }
+            AstNode::Match { scrutinee, arms } => {
+                self.resolve_node(scrutinee);
+                for arm in arms {
+                    match &arm.binding {
+                        Some(binding) => {
+                            self.scopes.push(HashSet::from([binding.clone()]));
+                            self.resolve_node(&mut arm.body);
+                            self.scopes.pop();
+                        }
+                        None => self.resolve_node(&mut arm.body),
+                    }
+                }
+            }
+            AstNode::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_node(condition);
+                self.resolve_node(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_node(else_branch);
+                }
+            }
+            AstNode::Switch { scrutinee, cases, default } => {
+                self.resolve_node(scrutinee);
+                for (value, body) in cases {
+                    self.resolve_node(value);
+                    self.resolve_node(body);
+                }
+                if let Some(default) = default {
+                    self.resolve_node(default);
+                }
+            }
+            AstNode::Labeled { body: node, .. } | AstNode::Group(node) | AstNode::Print(node) | AstNode::ExprStmt(node) | AstNode::Defer(node) => {
+                self.resolve_node(node)
+            }
+            // An imported file is resolved on its own when it is loaded.
+            AstNode::Boolean(_)
+            | AstNode::Nil
+            | AstNode::Number(_)
+            | AstNode::String(_)
+            | AstNode::Import(_)
+            | AstNode::Return(None)
+            | AstNode::Break(_)
+            | AstNode::Continue(_) => {}
+        }
+    }
+
+    fn resolve_local(
+        &self,
+        name: &str,
+    ) -> Option<usize> {
+        self.scopes.iter().rev().position(|scope| scope.contains(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::AstNode, environment::Environment, evaluating::EvaluateResult, lexing::lexing, parsing::Parser, resolving::Resolver, token::TokenType,
+    };
+
+    #[test]
+    fn shadowing() {
+        let tokens = lexing("tests/resolve.lox").unwrap();
+        let tokens = tokens.into_iter().filter(|token| !token.is_skippable()).collect::<Vec<TokenType>>();
+        let mut ast = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&mut ast);
+
+        // The assignment in the innermost block binds to the innermost `a`.
+        let AstNode::Block(outer) = &ast[1] else { panic!("expected block") };
+        let AstNode::Block(inner) = &outer[1] else { panic!("expected block") };
+        let AstNode::ExprStmt(assign) = &inner[1] else {
+            panic!("expected expression statement")
+        };
+        assert!(matches!(**assign, AstNode::Assign { depth: Some(0), .. }));
+        assert!(matches!(outer[2], AstNode::Print(ref node) if matches!(**node, AstNode::Identifier { depth: Some(0), .. })));
+        assert!(matches!(ast[2], AstNode::Print(ref node) if matches!(**node, AstNode::Identifier { depth: None, .. })));
+
+        let mut env = Environment::default();
+        let results = ast.iter().map(|node| node.evaluate(&mut env).unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                EvaluateResult::String("global".to_string()),
+                EvaluateResult::String("outer".to_string()),
+                EvaluateResult::String("global".to_string()),
+            ]
+        );
+    }
+}
@@ -13,21 +13,29 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    DotDot,
     Minus,
     Plus,
     Semicolon,
+    Colon,
     Star,
+    StarStar,
+    QuestionQuestion,
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    FatArrow,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
     Slash,
+    Percent,
     Space,
     Tab,
     NewLine,
@@ -35,6 +43,8 @@ pub enum TokenType {
     Number(Number),
     Identifier(String),
     KeyWord(KeyWord),
+    /// Never produced by the lexer: what the parser sees once every token has been consumed.
+    Eof,
 }
 
 impl PartialEq for TokenType {
@@ -47,25 +57,35 @@ impl PartialEq for TokenType {
             | (TokenType::RightParen, TokenType::RightParen)
             | (TokenType::LeftBrace, TokenType::LeftBrace)
             | (TokenType::RightBrace, TokenType::RightBrace)
+            | (TokenType::LeftBracket, TokenType::LeftBracket)
+            | (TokenType::RightBracket, TokenType::RightBracket)
             | (TokenType::Comma, TokenType::Comma)
             | (TokenType::Dot, TokenType::Dot)
+            | (TokenType::DotDot, TokenType::DotDot)
             | (TokenType::Minus, TokenType::Minus)
             | (TokenType::Plus, TokenType::Plus)
             | (TokenType::Semicolon, TokenType::Semicolon)
+            | (TokenType::Colon, TokenType::Colon)
             | (TokenType::Star, TokenType::Star)
+            | (TokenType::StarStar, TokenType::StarStar)
+            | (TokenType::QuestionQuestion, TokenType::QuestionQuestion)
             | (TokenType::Bang, TokenType::Bang)
             | (TokenType::BangEqual, TokenType::BangEqual)
             | (TokenType::Equal, TokenType::Equal)
             | (TokenType::EqualEqual, TokenType::EqualEqual)
+            | (TokenType::FatArrow, TokenType::FatArrow)
             | (TokenType::Greater, TokenType::Greater)
             | (TokenType::GreaterEqual, TokenType::GreaterEqual)
             | (TokenType::Less, TokenType::Less)
             | (TokenType::LessEqual, TokenType::LessEqual)
             | (TokenType::Slash, TokenType::Slash)
+            | (TokenType::Percent, TokenType::Percent)
             | (TokenType::Space, TokenType::Space)
             | (TokenType::Tab, TokenType::Tab)
-            | (TokenType::NewLine, TokenType::NewLine) => true,
+            | (TokenType::NewLine, TokenType::NewLine)
+            | (TokenType::Eof, TokenType::Eof) => true,
             (TokenType::String(s1), TokenType::String(s2)) => s1 == s2,
+            // `1` and `1.0` are distinct tokens; see `PartialEq for Number`.
             (TokenType::Number(n1), TokenType::Number(n2)) => n1 == n2,
             (TokenType::Identifier(s1), TokenType::Identifier(s2)) => s1 == s2,
             (TokenType::KeyWord(k1), TokenType::KeyWord(k2)) => k1 == k2,
@@ -81,17 +101,21 @@ impl TokenType {
             ')' => Ok(TokenType::RightParen),
             '{' => Ok(TokenType::LeftBrace),
             '}' => Ok(TokenType::RightBrace),
+            '[' => Ok(TokenType::LeftBracket),
+            ']' => Ok(TokenType::RightBracket),
             ',' => Ok(TokenType::Comma),
             '.' => Ok(TokenType::Dot),
             '-' => Ok(TokenType::Minus),
             '+' => Ok(TokenType::Plus),
             ';' => Ok(TokenType::Semicolon),
+            ':' => Ok(TokenType::Colon),
             '*' => Ok(TokenType::Star),
             '!' => Ok(TokenType::Bang),
             '=' => Ok(TokenType::Equal),
             '>' => Ok(TokenType::Greater),
             '<' => Ok(TokenType::Less),
             '/' => Ok(TokenType::Slash),
+            '%' => Ok(TokenType::Percent),
             ' ' => Ok(TokenType::Space),
             '\t' => Ok(TokenType::Tab),
             '\n' => Ok(TokenType::NewLine),
@@ -102,6 +126,21 @@ impl TokenType {
     pub fn is_skippable(&self) -> bool {
         matches!(self, TokenType::Space | TokenType::Tab | TokenType::NewLine)
     }
+
+    pub fn as_keyword(&self) -> Option<&KeyWord> {
+        match self {
+            TokenType::KeyWord(keyword) => Some(keyword),
+            _ => None,
+        }
+    }
+
+    /// Shorthand for comparing against `TokenType::KeyWord(keyword)`.
+    pub fn is_keyword(
+        &self,
+        keyword: KeyWord,
+    ) -> bool {
+        self.as_keyword() == Some(&keyword)
+    }
 }
 
 impl Display for TokenType {
@@ -114,21 +153,29 @@ impl Display for TokenType {
             TokenType::RightParen => ")".to_owned(),
             TokenType::LeftBrace => "{".to_owned(),
             TokenType::RightBrace => "}".to_owned(),
+            TokenType::LeftBracket => "[".to_owned(),
+            TokenType::RightBracket => "]".to_owned(),
             TokenType::Comma => ",".to_owned(),
             TokenType::Dot => ".".to_owned(),
+            TokenType::DotDot => "..".to_owned(),
             TokenType::Minus => "-".to_owned(),
             TokenType::Plus => "+".to_owned(),
             TokenType::Semicolon => ";".to_owned(),
+            TokenType::Colon => ":".to_owned(),
             TokenType::Star => "*".to_owned(),
+            TokenType::StarStar => "**".to_owned(),
+            TokenType::QuestionQuestion => "??".to_owned(),
             TokenType::Bang => "!".to_owned(),
             TokenType::BangEqual => "!=".to_owned(),
             TokenType::Equal => "=".to_owned(),
             TokenType::EqualEqual => "==".to_owned(),
+            TokenType::FatArrow => "=>".to_owned(),
             TokenType::Greater => ">".to_owned(),
             TokenType::GreaterEqual => ">=".to_owned(),
             TokenType::Less => "<".to_owned(),
             TokenType::LessEqual => "<=".to_owned(),
             TokenType::Slash => "/".to_owned(),
+            TokenType::Percent => "%".to_owned(),
             TokenType::Space => " ".to_owned(),
             TokenType::Tab => "\t".to_owned(),
             TokenType::NewLine => "\n".to_owned(),
@@ -136,6 +183,7 @@ impl Display for TokenType {
             TokenType::Number(number) => number.to_string(),
             TokenType::Identifier(s) => s.clone(),
             TokenType::KeyWord(keyword) => keyword.to_string(),
+            TokenType::Eof => "end of input".to_owned(),
         };
         write!(f, "{}", string)
     }
@@ -207,6 +255,196 @@ impl Div for Number {
     }
 }
 
+impl Number {
+    /// Integers are promoted, rounding to the nearest float beyond 2^53.
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Number::Integer(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    /// Integer overflow is an error rather than a panic or a silent wrap. An integer meeting a
+    /// float is promoted, so `1 + 2.5` is `3.5`.
+    pub fn checked_add(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(i1), Number::Integer(i2)) => match i1.checked_add(i2) {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in {} + {}", i1, i2),
+            },
+            _ => Ok(Number::Float(self.as_f64() + rhs.as_f64())),
+        }
+    }
+
+    pub fn checked_sub(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(i1), Number::Integer(i2)) => match i1.checked_sub(i2) {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in {} - {}", i1, i2),
+            },
+            _ => Ok(Number::Float(self.as_f64() - rhs.as_f64())),
+        }
+    }
+
+    pub fn checked_mul(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(i1), Number::Integer(i2)) => match i1.checked_mul(i2) {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in {} * {}", i1, i2),
+            },
+            _ => Ok(Number::Float(self.as_f64() * rhs.as_f64())),
+        }
+    }
+
+    pub fn checked_neg(self) -> anyhow::Result<Number> {
+        match self {
+            Number::Integer(i) => match i.checked_neg() {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in -({})", i),
+            },
+            Number::Float(f) => Ok(Number::Float(-f)),
+        }
+    }
+
+    pub fn checked_div(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (_, Number::Integer(0) | Number::Float(0.0)) => bail!("Division by zero"),
+            (Number::Integer(i1), Number::Integer(i2)) => match i1.checked_div(i2) {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in {} / {}", i1, i2),
+            },
+            _ => Ok(self / rhs),
+        }
+    }
+
+    /// A non-negative integer exponent keeps an integer base an integer (erroring on overflow),
+    /// while a negative one produces a float: `2 ** 3` is `8` but `2 ** -1` is `0.5`.
+    pub fn checked_pow(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(base), Number::Integer(exp)) if exp >= 0 => match u32::try_from(exp).ok().and_then(|exp| base.checked_pow(exp)) {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in {} ** {}", base, exp),
+            },
+            (Number::Integer(base), Number::Integer(exp)) => Ok(Number::Float((base as f64).powf(exp as f64))),
+            (Number::Integer(base), Number::Float(exp)) => Ok(Number::Float((base as f64).powf(exp))),
+            (Number::Float(base), Number::Integer(exp)) => Ok(Number::Float(base.powf(exp as f64))),
+            (Number::Float(base), Number::Float(exp)) => Ok(Number::Float(base.powf(exp))),
+        }
+    }
+
+    pub fn checked_rem(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (_, Number::Integer(0) | Number::Float(0.0)) => bail!("Remainder by zero"),
+            (Number::Integer(i1), Number::Integer(i2)) => match i1.checked_rem(i2) {
+                Some(i) => Ok(Number::Integer(i)),
+                None => bail!("Integer overflow in {} % {}", i1, i2),
+            },
+            // Mixed operands are promoted to floats, as in `checked_pow`.
+            _ => Ok(Number::Float(self.as_f64() % rhs.as_f64())),
+        }
+    }
+}
+
+/// Saturating arithmetic: integer results past `i64::MIN` or `i64::MAX` are clamped to that
+/// bound, so `i64::MAX + 1` is `i64::MAX`. By default every operator goes through its `checked_`
+/// form instead, which fails with "Integer overflow in ..." (`/` and `%` still do in this mode,
+/// their only overflow being `i64::MIN / -1`). Floats behave the same in every mode.
+impl Number {
+    pub fn saturating_add(
+        self,
+        rhs: Self,
+    ) -> Number {
+        match (self, rhs) {
+            (Number::Integer(i1), Number::Integer(i2)) => Number::Integer(i1.saturating_add(i2)),
+            _ => Number::Float(self.as_f64() + rhs.as_f64()),
+        }
+    }
+
+    pub fn saturating_sub(
+        self,
+        rhs: Self,
+    ) -> Number {
+        match (self, rhs) {
+            (Number::Integer(i1), Number::Integer(i2)) => Number::Integer(i1.saturating_sub(i2)),
+            _ => Number::Float(self.as_f64() - rhs.as_f64()),
+        }
+    }
+
+    pub fn saturating_mul(
+        self,
+        rhs: Self,
+    ) -> Number {
+        match (self, rhs) {
+            (Number::Integer(i1), Number::Integer(i2)) => Number::Integer(i1.saturating_mul(i2)),
+            _ => Number::Float(self.as_f64() * rhs.as_f64()),
+        }
+    }
+
+    /// Like `checked_pow`, but an integer result too large for `i64` is clamped.
+    pub fn saturating_pow(
+        self,
+        rhs: Self,
+    ) -> anyhow::Result<Number> {
+        match (self, rhs) {
+            (Number::Integer(base), Number::Integer(exp)) if exp >= 0 => {
+                Ok(Number::Integer(base.saturating_pow(u32::try_from(exp).unwrap_or(u32::MAX))))
+            }
+            _ => self.checked_pow(rhs),
+        }
+    }
+
+    pub fn saturating_neg(self) -> Number {
+        match self {
+            Number::Integer(i) => Number::Integer(i.saturating_neg()),
+            Number::Float(f) => Number::Float(-f),
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(value: i64) -> Self {
+        Number::Integer(value)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(value: f64) -> Self {
+        Number::Float(value)
+    }
+}
+
+/// A float converts only when it is a whole number within `i64`'s range, so `3.0` gives `3`
+/// but `3.5` is an error.
+impl TryFrom<Number> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Number) -> Result<Self, Self::Error> {
+        match value {
+            Number::Integer(i) => Ok(i),
+            Number::Float(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64 => Ok(f as i64),
+            Number::Float(f) => bail!("{} is not an integer", Number::Float(f)),
+        }
+    }
+}
+
 impl Neg for Number {
     type Output = Number;
 
@@ -225,12 +463,37 @@ impl PartialOrd for Number {
     ) -> Option<Ordering> {
         match (self, other) {
             (Number::Integer(i1), Number::Integer(i2)) => i1.partial_cmp(i2),
-            (Number::Float(f1), Number::Float(f2)) => f1.partial_cmp(f2),
-            _ => panic!("Cannot compare integer and float"),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
         }
     }
 }
 
+/// Floats whose magnitude is at least this are displayed in scientific notation.
+pub const SCIENTIFIC_ABOVE: f64 = 1e21;
+/// Non-zero floats whose magnitude is below this are displayed in scientific notation.
+pub const SCIENTIFIC_BELOW: f64 = 1e-6;
+
+/// Formats `value` in plain decimal, unless its magnitude is at least `above` or (for non-zero
+/// values) below `below`, where it switches to scientific notation: `1e21` rather than
+/// `1000000000000000000000`. Infinities print as `Infinity` and `-Infinity` and NaN as `NaN`, as
+/// in JavaScript, rather than Rust's `inf`.
+pub fn format_float(
+    value: f64,
+    below: f64,
+    above: f64,
+) -> String {
+    let magnitude = value.abs();
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if magnitude.is_infinite() {
+        format!("{}Infinity", if value < 0.0 { "-" } else { "" })
+    } else if magnitude >= above || (magnitude != 0.0 && magnitude < below) {
+        format!("{:e}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
 impl Display for Number {
     fn fmt(
         &self,
@@ -238,11 +501,13 @@ impl Display for Number {
     ) -> std::fmt::Result {
         match self {
             Number::Integer(i) => write!(f, "{}", i),
-            Number::Float(fl) => write!(f, "{}", fl),
+            Number::Float(fl) => write!(f, "{}", format_float(*fl, SCIENTIFIC_BELOW, SCIENTIFIC_ABOVE)),
         }
     }
 }
 
+/// Structural: an integer never equals a float, so `1` and `1.0` are different tokens (and
+/// different values). Ordering, unlike equality, promotes mixed operands; see `PartialOrd`.
 impl PartialEq for Number {
     fn eq(
         &self,
@@ -273,7 +538,22 @@ pub enum KeyWord {
     This,
     True,
     Var,
+    Const,
     While,
+    Switch,
+    Case,
+    Default,
+    In,
+    Import,
+    Repeat,
+    Defer,
+    Unless,
+    Until,
+    Global,
+    Match,
+    Then,
+    Break,
+    Continue,
 }
 
 impl FromStr for KeyWord {
@@ -296,7 +576,22 @@ impl FromStr for KeyWord {
             "this" => Ok(KeyWord::This),
             "true" => Ok(KeyWord::True),
             "var" => Ok(KeyWord::Var),
+            "const" => Ok(KeyWord::Const),
             "while" => Ok(KeyWord::While),
+            "switch" => Ok(KeyWord::Switch),
+            "case" => Ok(KeyWord::Case),
+            "default" => Ok(KeyWord::Default),
+            "in" => Ok(KeyWord::In),
+            "import" => Ok(KeyWord::Import),
+            "repeat" => Ok(KeyWord::Repeat),
+            "defer" => Ok(KeyWord::Defer),
+            "unless" => Ok(KeyWord::Unless),
+            "until" => Ok(KeyWord::Until),
+            "global" => Ok(KeyWord::Global),
+            "match" => Ok(KeyWord::Match),
+            "then" => Ok(KeyWord::Then),
+            "break" => Ok(KeyWord::Break),
+            "continue" => Ok(KeyWord::Continue),
             _ => bail!("Invalid keyword: {}", s),
         }
     }
@@ -323,7 +618,22 @@ impl Display for KeyWord {
             KeyWord::This => "this".to_owned(),
             KeyWord::True => "true".to_owned(),
             KeyWord::Var => "var".to_owned(),
+            KeyWord::Const => "const".to_owned(),
             KeyWord::While => "while".to_owned(),
+            KeyWord::Switch => "switch".to_owned(),
+            KeyWord::Case => "case".to_owned(),
+            KeyWord::Default => "default".to_owned(),
+            KeyWord::In => "in".to_owned(),
+            KeyWord::Import => "import".to_owned(),
+            KeyWord::Repeat => "repeat".to_owned(),
+            KeyWord::Defer => "defer".to_owned(),
+            KeyWord::Unless => "unless".to_owned(),
+            KeyWord::Until => "until".to_owned(),
+            KeyWord::Global => "global".to_owned(),
+            KeyWord::Match => "match".to_owned(),
+            KeyWord::Then => "then".to_owned(),
+            KeyWord::Break => "break".to_owned(),
+            KeyWord::Continue => "continue".to_owned(),
         };
         write!(f, "{}", string)
     }
@@ -342,4 +652,130 @@ mod tests {
         println!("{}", res1);
         println!("{}", res2);
     }
+
+    #[test]
+    fn checked_div_rem() {
+        use super::Number;
+
+        let err = Number::Integer(i64::MIN).checked_div(Number::Integer(-1)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in -9223372036854775808 / -1");
+        let err = Number::Integer(5).checked_div(Number::Integer(0)).unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        let err = Number::Integer(5).checked_rem(Number::Integer(0)).unwrap_err();
+        assert_eq!(err.to_string(), "Remainder by zero");
+        assert_eq!(Number::Integer(7).checked_rem(Number::Integer(3)).unwrap(), Number::Integer(1));
+        assert_eq!(Number::Float(5.0).checked_div(Number::Float(2.0)).unwrap(), Number::Float(2.5));
+        assert_eq!(Number::Integer(5).checked_rem(Number::Float(2.0)).unwrap(), Number::Float(1.0));
+        assert_eq!(Number::Float(5.5).checked_rem(Number::Integer(2)).unwrap(), Number::Float(1.5));
+        let err = Number::Float(5.5).checked_rem(Number::Integer(0)).unwrap_err();
+        assert_eq!(err.to_string(), "Remainder by zero");
+    }
+
+    #[test]
+    fn checked_add_sub_mul_neg() {
+        use super::Number;
+
+        let max = Number::Integer(i64::MAX);
+        let min = Number::Integer(i64::MIN);
+        let err = max.checked_add(Number::Integer(1)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in 9223372036854775807 + 1");
+        let err = min.checked_sub(Number::Integer(1)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in -9223372036854775808 - 1");
+        let err = max.checked_mul(Number::Integer(2)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in 9223372036854775807 * 2");
+        let err = min.checked_neg().unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in -(-9223372036854775808)");
+        assert_eq!(Number::Integer(2).checked_mul(Number::Integer(3)).unwrap(), Number::Integer(6));
+        assert_eq!(Number::Integer(1).checked_add(Number::Float(2.5)).unwrap(), Number::Float(3.5));
+        assert_eq!(Number::Float(1.0).checked_sub(Number::Integer(3)).unwrap(), Number::Float(-2.0));
+    }
+
+    #[test]
+    fn saturating() {
+        use super::Number;
+
+        let max = Number::Integer(i64::MAX);
+        let min = Number::Integer(i64::MIN);
+        assert_eq!(max.saturating_add(Number::Integer(1)), max);
+        assert_eq!(min.saturating_sub(Number::Integer(1)), min);
+        assert_eq!(max.saturating_mul(Number::Integer(-2)), min);
+        assert_eq!(min.saturating_neg(), max);
+        assert_eq!(Number::Integer(2).saturating_pow(Number::Integer(64)).unwrap(), max);
+        assert_eq!(Number::Integer(-2).saturating_pow(Number::Integer(63)).unwrap(), min);
+        assert_eq!(Number::Integer(2).saturating_pow(Number::Integer(-1)).unwrap(), Number::Float(0.5));
+        assert_eq!(Number::Integer(2).saturating_add(Number::Integer(3)), Number::Integer(5));
+        assert_eq!(Number::Float(1.5).saturating_add(Number::Float(f64::MAX)), Number::Float(f64::MAX));
+    }
+
+    #[test]
+    fn checked_pow() {
+        use super::Number;
+
+        assert_eq!(Number::Integer(2).checked_pow(Number::Integer(3)).unwrap(), Number::Integer(8));
+        assert_eq!(Number::Integer(2).checked_pow(Number::Integer(-2)).unwrap(), Number::Float(0.25));
+        let err = Number::Integer(2).checked_pow(Number::Integer(64)).unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow in 2 ** 64");
+    }
+
+    #[test]
+    fn display_scientific() {
+        use super::{format_float, Number};
+
+        assert_eq!(Number::Float(1e21).to_string(), "1e21");
+        assert_eq!(Number::Float(-1e21).to_string(), "-1e21");
+        assert_eq!(Number::Float(1e-7).to_string(), "1e-7");
+        assert_eq!(Number::Float(1e20).to_string(), "100000000000000000000");
+        assert_eq!(Number::Float(0.000001).to_string(), "0.000001");
+        assert_eq!(Number::Float(0.0).to_string(), "0");
+        assert_eq!(format_float(1234.5, 1.0, 1000.0), "1.2345e3");
+    }
+
+    #[test]
+    fn display_non_finite() {
+        use super::Number;
+
+        assert_eq!(Number::Float(f64::INFINITY).to_string(), "Infinity");
+        assert_eq!(Number::Float(f64::NEG_INFINITY).to_string(), "-Infinity");
+        assert_eq!(Number::Float(f64::NAN).to_string(), "NaN");
+        assert_eq!(Number::Float(-f64::NAN).to_string(), "NaN");
+        assert_eq!(Number::Float(f64::MAX).to_string(), "1.7976931348623157e308");
+    }
+
+    #[test]
+    fn conversions() {
+        use super::Number;
+
+        assert_eq!(Number::from(3), Number::Integer(3));
+        assert_eq!(Number::from(2.5), Number::Float(2.5));
+        assert_eq!(i64::try_from(Number::Integer(-7)).unwrap(), -7);
+        assert_eq!(i64::try_from(Number::Float(3.0)).unwrap(), 3);
+        assert_eq!(i64::try_from(Number::Float(3.5)).unwrap_err().to_string(), "3.5 is not an integer");
+        assert!(i64::try_from(Number::Float(f64::NAN)).is_err());
+        assert!(i64::try_from(Number::Float(1e20)).is_err());
+        assert_eq!(Number::Integer(2).as_f64(), 2.0);
+        assert_eq!(Number::Float(2.5).as_f64(), 2.5);
+    }
+
+    #[test]
+    fn mixed_number_tokens() {
+        use super::{Number, TokenType};
+
+        assert_ne!(TokenType::Number(Number::Integer(1)), TokenType::Number(Number::Float(1.0)));
+        assert_eq!(TokenType::Number(Number::Float(1.0)), TokenType::Number(Number::Float(1.0)));
+        assert_eq!(Number::Integer(1).partial_cmp(&Number::Float(1.0)), Some(std::cmp::Ordering::Equal));
+    }
+
+    #[test]
+    fn keyword_helpers() {
+        use super::{KeyWord, TokenType};
+
+        let token = TokenType::KeyWord(KeyWord::If);
+        assert_eq!(token.as_keyword(), Some(&KeyWord::If));
+        assert!(token.is_keyword(KeyWord::If));
+        assert!(!token.is_keyword(KeyWord::While));
+
+        let token = TokenType::Identifier("if_".to_string());
+        assert_eq!(token.as_keyword(), None);
+        assert!(!token.is_keyword(KeyWord::If));
+    }
 }
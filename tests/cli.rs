@@ -0,0 +1,154 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn stdin() {
+    for flag in ["-", "--stdin"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .arg(flag)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"var a = 1;\nprint a + 2;\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"1").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Expected ';' after expression"));
+}
+
+#[test]
+fn file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg("tests/statement.lox").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "quz89\n1395\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg("tests/division.lox").output().unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn warnings() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"1;\nprint 2;\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "warning: statement has no effect\n");
+}
+
+#[test]
+fn version_and_help() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg("--version").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("lox {}\n", env!("CARGO_PKG_VERSION")));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg("--help").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("Usage: lox"));
+}
+
+#[test]
+fn trace() {
+    let plain = Command::new(env!("CARGO_BIN_EXE_lox")).arg("tests/statement.lox").output().unwrap();
+    let traced = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(["--trace", "tests/statement.lox"])
+        .output()
+        .unwrap();
+    assert!(traced.status.success());
+    assert_eq!(traced.stdout, plain.stdout);
+    assert!(plain.stderr.is_empty());
+
+    let stderr = String::from_utf8(traced.stderr).unwrap();
+    assert!(stderr.lines().all(|line| line.starts_with("[trace] ")));
+    assert!(stderr.contains("[trace] Print baz => 1395"));
+}
+
+#[test]
+fn max_errors() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox")).arg("tests/errors.lox").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("Expected expression").count(), 20);
+    assert!(stderr.contains("too many errors; stopping"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(["--max-errors", "3", "tests/errors.lox"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("Expected expression").count(), 3);
+    assert!(stderr.contains("too many errors; stopping"));
+}
+
+#[test]
+fn return_exit_code() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"print 1;\nreturn 3;\nprint 2;\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+
+    for code in ["256", "-1", "4294967297"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .args(["--no-color", "-"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(format!("return {};", code).as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            format!("Error: exit code must be between 0 and 255, got {}\n", code)
+        );
+    }
+}
+
+#[test]
+fn color() {
+    let plain = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(["--no-color", "tests/division.lox"])
+        .output()
+        .unwrap();
+    assert!(!plain.status.success());
+    let stderr = String::from_utf8(plain.stderr).unwrap();
+    assert!(stderr.starts_with("Error: "));
+    assert!(!stderr.contains('\x1b'));
+
+    let colored = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(["--color", "tests/division.lox"])
+        .output()
+        .unwrap();
+    assert_eq!(colored.status.code(), Some(1));
+    assert!(String::from_utf8(colored.stderr).unwrap().contains("\x1b[1;31mError:\x1b[0m"));
+
+    // Captured stderr isn't a terminal, so color is off unless asked for.
+    let detected = Command::new(env!("CARGO_BIN_EXE_lox")).arg("tests/division.lox").output().unwrap();
+    assert_eq!(String::from_utf8(detected.stderr).unwrap(), stderr);
+}